@@ -6,6 +6,7 @@
 use mightybuga_bsc as board;
 use mightybuga_bsc::prelude::*;
 use mightybuga_bsc::timer::SysDelay;
+use mightybuga_bsc::timer_based_buzzer::pitch::{midi_note, NoteName};
 use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
 use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzerInterface;
 use mightybuga_bsc::EncoderController;
@@ -24,7 +25,7 @@ use embedded_alloc::LlffHeap as Heap;
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
 
-use logging::Logger;
+use logging::{log, logln, Fixed, Logger};
 
 #[entry]
 fn main() -> ! {
@@ -108,20 +109,19 @@ fn main() -> ! {
                     logger.log("Light sensor values: ");
                     let light_map = light_sensor_array.get_light_map();
                     light_sensor_array.set_led(false);
-                    for i in 0..8 {
-                        logger.log(" ");
-                        print_number(light_map[i] as isize, &mut logger);
+                    for value in light_map {
+                        log!(logger, " {}", value);
                     }
-                    logger.log("\r\n");
+                    logln!(logger);
                 }
                 b'h' => {
                     // Read the battery sensor
-                    logger.log("Battery sensor value: ");
-                    print_number(
-                        battery_sensor.get_battery_millivolts() as isize,
-                        &mut logger,
+                    let millivolts = battery_sensor.get_battery_millivolts();
+                    logln!(
+                        logger,
+                        "Battery sensor value: {} Volts",
+                        Fixed::new(millivolts as i32, 3)
                     );
-                    logger.log(" milli Volts\r\n");
                 }
                 _ => {
                     // Print the menu
@@ -155,37 +155,17 @@ fn print_menu(logger: &mut Logger) {
     logger.log("   Any other key prints this menu\r\n");
 }
 
-// Note struct defines the prescaler and counter values for a given note
-struct Note {
-    prescaler: u16,
-    counter: u16,
-}
-
 // function that plays some notes with the buzzer
 fn play_notes(logger: &mut Logger, buzzer: &mut TimerBasedBuzzer, delay: &mut SysDelay) {
     logger.log("Playing some notes with the buzzer\r\n");
 
-    // Define the notes
-    const C: Note = Note {
-        prescaler: 70,
-        counter: 2052,
-    };
-    const D: Note = Note {
-        prescaler: 70,
-        counter: 1828,
-    };
-    const E: Note = Note {
-        prescaler: 70,
-        counter: 1629,
-    };
-
     // Play the notes
     buzzer.turn_on();
-    buzzer.change_frequency(C.prescaler, C.counter);
+    buzzer.set_note(midi_note(NoteName::C, 5));
     delay.delay(300.millis());
-    buzzer.change_frequency(D.prescaler, D.counter);
+    buzzer.set_note(midi_note(NoteName::D, 5));
     delay.delay(300.millis());
-    buzzer.change_frequency(E.prescaler, E.counter);
+    buzzer.set_note(midi_note(NoteName::E, 5));
     delay.delay(300.millis());
     buzzer.turn_off();
 }
@@ -202,11 +182,7 @@ fn read_encoder(
     loop {
         let (delta, steps) = encoder.delta();
         if last != steps {
-            logger.log("(steps,delta): (");
-            print_number(steps, logger);
-            logger.log(",");
-            print_number(delta, logger);
-            logger.log(")\r\n");
+            logln!(logger, "(steps,delta): ({},{})", steps, delta);
             last = steps;
         }
 
@@ -214,30 +190,3 @@ fn read_encoder(
         delay.delay(20.millis());
     }
 }
-
-fn print_number(n: isize, logger: &mut Logger) {
-    let mut len = 0;
-    let mut digits = [0_u8; 20];
-    let mut d = 0;
-    let mut binary = n.abs();
-    while binary > 0 {
-        digits[d] = (binary % 10) as u8;
-        d += 1;
-        binary /= 10;
-    }
-    if d == 0 {
-        d = 1;
-    }
-    let mut ascii = [0_u8; 20];
-    if n < 0 {
-        ascii[0] = b'-';
-        len = 1;
-    }
-    while d > 0 {
-        d -= 1;
-        ascii[len] = digits[d] + b'0';
-        len += 1;
-    }
-    let s = core::str::from_utf8(ascii[0..len].as_ref()).unwrap();
-    logger.log(s);
-}
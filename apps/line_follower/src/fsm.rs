@@ -1,5 +1,6 @@
 use crate::LineFollowerStatus;
-use logging::Logger;
+use led_patterns::{PairPattern, Pattern};
+use logging::{error, warn, Logger};
 
 use crate::fsm_states;
 
@@ -9,6 +10,7 @@ pub enum FSMState {
     Calibration,
     LineFollowing,
     BatteryLow,
+    SettingsMenu,
 }
 
 #[derive(Clone, Copy)]
@@ -16,7 +18,12 @@ pub enum FSMEvent {
     NothingHappened,
     Button1Pressed,
     Button2Pressed,
-    BatteryIsLow,
+    Buttons1And3Pressed,
+    CalibrationSelected,
+    BatteryWarning,
+    BatteryCritical,
+    LineLost,
+    SensorFailure,
 }
 
 impl FSMState {
@@ -28,32 +35,75 @@ impl FSMState {
         match (self, event) {
             (FSMState::Idle, FSMEvent::Button1Pressed) => FSMState::HardwareCheck,
             (FSMState::Idle, FSMEvent::Button2Pressed) => FSMState::Calibration,
-            (FSMState::Idle, FSMEvent::BatteryIsLow) => FSMState::BatteryLow,
+            (FSMState::Idle, FSMEvent::Buttons1And3Pressed) => FSMState::SettingsMenu,
+            (FSMState::Idle, FSMEvent::BatteryWarning) => FSMState::Idle,
+            (FSMState::Idle, FSMEvent::BatteryCritical) => FSMState::BatteryLow,
 
-            (FSMState::HardwareCheck, FSMEvent::NothingHappened) => FSMState::Idle,
-            (FSMState::HardwareCheck, FSMEvent::BatteryIsLow) => FSMState::BatteryLow,
+            (FSMState::HardwareCheck, FSMEvent::NothingHappened) => {
+                status.light_sensor_failure = false;
+                FSMState::Idle
+            }
+            (FSMState::HardwareCheck, FSMEvent::BatteryCritical) => FSMState::BatteryLow,
+            (FSMState::HardwareCheck, FSMEvent::SensorFailure) => {
+                status.light_sensor_failure = true;
+                FSMState::Idle
+            }
 
+            // The robot can't follow the line with a dead sensor
+            (FSMState::Calibration, FSMEvent::Button1Pressed) if status.light_sensor_failure => {
+                let mut logger = Logger::new(&mut status.board.serial.tx)
+                    .tagged("fsm")
+                    .filtered(&status.log_filter)
+                    .timestamped(&status.log_clock);
+                error!(logger, "A light sensor is dead, check the hardware again");
+                FSMState::Idle
+            }
             (FSMState::Calibration, FSMEvent::Button1Pressed) => FSMState::LineFollowing,
             (FSMState::Calibration, FSMEvent::Button2Pressed) => FSMState::Idle,
-            (FSMState::Calibration, FSMEvent::BatteryIsLow) => FSMState::BatteryLow,
+            (FSMState::Calibration, FSMEvent::BatteryCritical) => FSMState::BatteryLow,
 
             (FSMState::LineFollowing, FSMEvent::Button2Pressed) => FSMState::Idle,
-            (FSMState::LineFollowing, FSMEvent::BatteryIsLow) => FSMState::BatteryLow,
+            (FSMState::LineFollowing, FSMEvent::BatteryCritical) => FSMState::BatteryLow,
+            (FSMState::LineFollowing, FSMEvent::LineLost) => FSMState::Idle,
+
+            (FSMState::SettingsMenu, FSMEvent::Button2Pressed) => FSMState::Idle,
+            (FSMState::SettingsMenu, FSMEvent::CalibrationSelected) => FSMState::Calibration,
+            (FSMState::SettingsMenu, FSMEvent::BatteryCritical) => FSMState::BatteryLow,
 
             (_s, _e) => {
-                Logger::new(&mut status.board.serial.tx).log("default to idle state\r\n");
+                let mut logger = Logger::new(&mut status.board.serial.tx)
+                    .tagged("fsm")
+                    .filtered(&status.log_filter)
+                    .timestamped(&status.log_clock);
+                warn!(logger, "default to idle state");
                 FSMState::Idle
             }
         }
     }
 
+    // Patterns of the leds D1 and D2 while the state runs
+    pub fn led_pattern(&self) -> PairPattern {
+        match *self {
+            FSMState::Idle => PairPattern::new(Pattern::Solid, Pattern::Off),
+            FSMState::HardwareCheck => PairPattern::alternating(200),
+            FSMState::Calibration => PairPattern::new(Pattern::Heartbeat, Pattern::Off),
+            // Countdown before starting to move, then the leds show the position of the line
+            FSMState::LineFollowing => PairPattern::alternating(100),
+            FSMState::BatteryLow => PairPattern::alternating(1000),
+            // The leds show the number of the menu item
+            FSMState::SettingsMenu => PairPattern::both(Pattern::Off),
+        }
+    }
+
     pub fn run<'a>(&self, status: &mut LineFollowerStatus) -> FSMEvent {
+        status.leds.set(self.led_pattern(), status.board.clock.now_ms());
         match *self {
             FSMState::Idle {} => fsm_states::idle::run(status),
             FSMState::HardwareCheck {} => fsm_states::hardware_check::run(status),
             FSMState::Calibration {} => fsm_states::calibration::run(status),
             FSMState::LineFollowing {} => fsm_states::line_following::run(status),
             FSMState::BatteryLow {} => fsm_states::battery_low::run(status),
+            FSMState::SettingsMenu {} => fsm_states::settings_menu::run(status),
         }
     }
 }
@@ -2,62 +2,59 @@
 ///
 /// The battery low state is the state where the line follower is in when the battery is low.
 /// Here the line follower waits for the user to change the battery, it currently blinks the leds
-/// D1 and D2, prints a message to the user and uses the buzzer.
+/// D1 and D2 alternating, prints a message to the user and plays a melody with the buzzer. The leds
+/// and the melody don't block the state, so the battery is checked all the time.
 ///
-/// If the battery is no longer low, the line follower will transition to the idle state.
+/// If the battery is no longer low (its state of charge has risen above the critical threshold plus
+/// the hysteresis margin for a while), the line follower will transition to the idle state.
 ///
 /// The state output events are:
 /// - NothingHappend: When the battery is no longer low.
-use crate::board::timer::SysDelay;
-use mightybuga_bsc::prelude::*;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzerInterface;
+use mightybuga_bsc::timer_based_buzzer::melody::Melody;
+use mightybuga_bsc::timer_based_buzzer::rtttl::{self, Ringtone};
 
 use battery_sensor_controller::BatterySensorController;
 
 use crate::fsm::FSMEvent;
 use crate::line_follower_status::LineFollowerStatus;
 
-use logging::Logger;
+use logging::{warn, Logger};
 
-pub fn run(status: & mut  LineFollowerStatus) -> FSMEvent {
-    let mut logger = Logger::new(&mut status.board.serial.tx);
+// The melody is played while the battery is low, without blocking the battery checks
+static BATTERY_LOW_MELODY: Ringtone<3> = rtttl::ringtone("battery low:d=2,o=5,b=60:d,f,4p.");
+// Period of the message to the user
+const REMINDER_PERIOD_MS: u32 = 5500;
+
+pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("battery_low")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     logger.log("Battery low state\r\n");
 
+    let mut reminder_ms = status.board.clock.now_ms();
+    status.melody_player.play(
+        &mut status.board.buzzer,
+        Melody::looping(BATTERY_LOW_MELODY.notes()),
+        reminder_ms,
+    );
+    logger.log("Battery is low, please change the battery\r\n");
+
     loop {
-        status.board.led_d1.toggle();
-        status.board.led_d2.toggle();
-        logger.log("Battery is low, please change the battery\r\n");
-        play_notes(&mut status.board.buzzer, &mut status.board.delay);
-        status.board.delay.delay_ms(1500u32);
+        let now_ms = status.board.clock.now_ms();
+        status.melody_player.tick(&mut status.board.buzzer, now_ms);
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
+        if now_ms.wrapping_sub(reminder_ms) >= REMINDER_PERIOD_MS {
+            reminder_ms = now_ms;
+            warn!(logger, "Battery is low, please change the battery");
+        }
 
         if !status.board.battery_sensor.is_battery_low() {
+            status.melody_player.stop(&mut status.board.buzzer);
             logger.log("Battery is no longer low\r\n");
+            // The battery has been changed, the history of the old one is not useful anymore
+            status.battery.monitor.clear();
             return FSMEvent::NothingHappened;
         }
     }
 }
-
-// Note struct defines the prescaler and counter values for a given note
-struct Note {
-    prescaler: u16,
-    counter: u16,
-}
-
-fn play_notes(buzzer: &mut TimerBasedBuzzer, delay: &mut SysDelay) {
-    const D: Note = Note {
-        prescaler: 70,
-        counter: 1828,
-    };
-    const F: Note = Note {
-        prescaler: 70,
-        counter: 1532,
-    };
-
-    buzzer.turn_on();
-    buzzer.change_frequency(D.prescaler, D.counter);
-    delay.delay_ms(2000u32);
-    buzzer.change_frequency(F.prescaler, F.counter);
-    delay.delay_ms(2000u32);
-    buzzer.turn_off();
-}
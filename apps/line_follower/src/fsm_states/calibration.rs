@@ -2,42 +2,66 @@
 ///
 /// The calibration state is the state where the line follower is in when the user wants to calibrate
 /// the line follower. Here the line follower waits for the user to press the button 1 to start the
-/// calibration process.
+/// calibration process. The buttons must be clicked (pressed and released), so the button pressed to
+/// enter the state doesn't start the calibration.
 ///
-/// Once the calibration is done, the line follower beeps and waits for the user inputs.
+/// During the calibration, the user must sweep the robot over the line so every sensor sees both
+/// the line and the background. The minimum and maximum values of each sensor and the polarity of
+/// the line (dark line on a light surface or light line on a dark surface) are stored in the line
+/// follower status. If some sensor has not seen enough contrast, the previous calibration is kept.
+///
+/// The led D1 shows a heartbeat during the whole state.
+///
+/// Once the calibration is done, the line follower plays the calibration done cue (or the
+/// calibration fault code if it failed) and waits for the user inputs.
 ///
 /// The user can also use the serial rx to send the button 1 or button 2 command to the line follower.
 ///
 /// The state output events are:
 /// - Button1Pressed: When the user presses the button 1.
 /// - Button2Pressed: When the user presses the button 2.
-/// - BatteryIsLow: When the battery is low.
+/// - BatteryCritical: When the battery is low.
 ///
-use crate::board::timer::SysDelay;
-use hal_button::ButtonController;
+use hal_button::{ButtonController, ButtonEvent};
+use mightybuga_bsc::button_events::ButtonId;
 use mightybuga_bsc::prelude::*;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzerInterface;
+use mightybuga_bsc::timer_based_buzzer::status_sounds::Cue;
+
+use light_sensor_array_controller::calibration::Calibrator;
+use light_sensor_array_controller::line_position::LinePolarity;
+use light_sensor_array_controller::LightSensorArrayController;
 
 use crate::fsm::FSMEvent;
 use crate::line_follower_status::LineFollowerStatus;
+use crate::status_sounds::{self, CALIBRATION_FAULT};
+
+use logging::{warn, Logger};
 
-use logging::Logger;
+// Time sampling the sensors while the user sweeps the robot over the line
+const CALIBRATION_TIME_MS: u32 = 3000;
+const CALIBRATION_SAMPLE_PERIOD_MS: u32 = 10;
 
 pub fn run(status: & mut  LineFollowerStatus) -> FSMEvent {
-    let mut logger = Logger::new(&mut status.board.serial.tx);
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("calibration")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     logger.log("Calibration state\r\n");
 
     logger.log("Press button 1 to start calibration\r\n");
 
-    // Wait for buttons to be released before waiting for buttons to be pressed
-    status.board.delay.delay_ms(2000u32);
+    // The button pressed to enter this state may still be pressed, it must be clicked again
+    let now_ms = status.board.clock.now_ms();
+    status.board.btn_1.reset_events(now_ms);
+    status.board.btn_2.reset_events(now_ms);
 
     loop {
-        if status.board.btn_1.is_pressed() {
+        let now_ms = status.board.clock.now_ms();
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
+        if let Some(ButtonEvent::Click) = status.board.btn_1.poll_event(now_ms) {
             break;
         }
-        if status.board.btn_2.is_pressed() {
+        if let Some(ButtonEvent::Click) = status.board.btn_2.poll_event(now_ms) {
             logger.log("Exit calibration\r\n");
             return FSMEvent::Button2Pressed;
         }
@@ -53,25 +77,47 @@ pub fn run(status: & mut  LineFollowerStatus) -> FSMEvent {
         }
     }
 
-    logger.log("Calibration started\r\n");
-    // Calibration here
-    status.board.delay.delay_ms(3000u32);
-
-    logger.log("Calibration done\r\n");
+    logger.log("Calibration started, sweep the robot over the line\r\n");
+    let mut calibrator = Calibrator::new();
+    status.board.light_sensor_array.set_led(true);
+    for _ in 0..CALIBRATION_TIME_MS / CALIBRATION_SAMPLE_PERIOD_MS {
+        calibrator.update(&status.board.light_sensor_array.get_light_map());
+        let now_ms = status.board.clock.now_ms();
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
+        status.board.delay.delay_ms(CALIBRATION_SAMPLE_PERIOD_MS);
+    }
+    status.board.light_sensor_array.set_led(false);
 
-    beep(&mut status.board.buzzer, &mut status.board.delay);
-    status.board.delay.delay_ms(50u32);
-    beep(&mut status.board.buzzer, &mut status.board.delay);
+    let buzzer = &mut status.board.buzzer;
+    let delay = &mut status.board.delay;
+    match calibrator.finish() {
+        Some(calibration) => {
+            status.calibration = calibration;
+            match calibration.polarity {
+                LinePolarity::DarkLine => logger.log("Calibration done: dark line\r\n"),
+                LinePolarity::LightLine => logger.log("Calibration done: light line\r\n"),
+            }
+            status_sounds::play(buzzer, delay, Cue::CalibrationDone.notes());
+        }
+        None => {
+            warn!(logger, "Calibration failed: not enough contrast, keeping the previous one");
+            status_sounds::play(buzzer, delay, CALIBRATION_FAULT.notes());
+        }
+    }
 
     logger.log("Press button 1 to start line following\r\n");
     logger.log("Press button 2 to go back to idle\r\n");
+    status.board.button_events.clear();
     loop {
-        if status.board.btn_1.is_pressed() {
-            return FSMEvent::Button1Pressed;
-        }
-        if status.board.btn_2.is_pressed() {
-            logger.log("Exit calibration\r\n");
-            return FSMEvent::Button2Pressed;
+        let now_ms = status.board.clock.now_ms();
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
+        match status.board.button_events.pop_press() {
+            Some(ButtonId::Button1) => return FSMEvent::Button1Pressed,
+            Some(ButtonId::Button2) => {
+                logger.log("Exit calibration\r\n");
+                return FSMEvent::Button2Pressed;
+            }
+            _ => {}
         }
         if let Ok(serial_input) = status.board.serial.rx.read() {
             match serial_input {
@@ -82,10 +128,3 @@ pub fn run(status: & mut  LineFollowerStatus) -> FSMEvent {
         }
     }
 }
-
-fn beep(buzzer: &mut TimerBasedBuzzer, delay: &mut SysDelay) {
-    buzzer.turn_on();
-    buzzer.change_frequency(70, 1828);
-    delay.delay_ms(100u32);
-    buzzer.turn_off();
-}
@@ -1,62 +1,169 @@
 /// Hardware check state
 ///
 /// In this state, a number of checks related to the integrity of the hardware are performed.
-/// Currently, there are no checks implemented, so we wait for a second, do a pair of LED toggles
-/// and beep the buzzer. Then we exti with the NothingHappend or BatteryIsLow event.
+/// The LEDs blink alternating and the buzzer beeps so the user can check them, and the light sensor array
+/// is checked reading it with the emitter LED off and on (the robot must be over a uniform light
+/// surface). A report of every sensor is printed through the serial port and, if some sensor is
+/// dead, the buzzer plays its fault code (see status_sounds) and the led D1 blinks the number of the
+/// sensor (1 to 8) once, otherwise the buzzer plays the hardware check ok cue.
 ///
 /// The state output events are:
-/// - BatteryIsLow: When the battery is low.
+/// - BatteryCritical: When the battery is low.
+/// - SensorFailure: When a sensor of the light sensor array is dead. The line following is not
+///   started until a hardware check passes.
 /// - NothingHappend: When all checks are done.
 use crate::board::timer::SysDelay;
 use battery_sensor_controller::BatterySensorController;
+use led_patterns::{PairPattern, PairRunner, Pattern};
+use light_sensor_array_controller::diagnostics::{diagnose, ChannelStatus};
+use light_sensor_array_controller::{AcquisitionMode, LightSensorArrayController};
+use mightybuga_bsc::clock::Clock;
+use mightybuga_bsc::gpio::{Output, Pin};
 use mightybuga_bsc::prelude::*;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
-use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzerInterface;
+use mightybuga_bsc::timer_based_buzzer::status_sounds::Cue;
 
 use crate::fsm::FSMEvent;
 use crate::line_follower_status::LineFollowerStatus;
+use crate::status_sounds::{self, light_sensor_fault};
 
-use logging::Logger;
+use logging::{error, Logger};
 
 pub fn run(status: & mut  LineFollowerStatus) -> FSMEvent {
-    let mut logger = Logger::new(&mut status.board.serial.tx);
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("hardware_check")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     logger.log("Hardware check state\r\n");
 
     {
-        logger.log("Checking hardware 1\r\n");
-        for _ in 0..5 {
-            status.board.led_d1.toggle();
-            status.board.delay.delay_ms(100u32);
-        }
+        logger.log("Checking the leds\r\n");
+        show_leds(
+            &mut status.leds,
+            &mut status.board.led_d1,
+            &mut status.board.led_d2,
+            &status.board.clock,
+            &mut status.board.delay,
+            LED_CHECK_MS,
+        );
     }
 
+    let mut dead_sensor = None;
     {
-        logger.log("Checking hardware 2\r\n");
-        for _ in 0..5 {
-            status.board.led_d2.toggle();
-            status.board.delay.delay_ms(100u32);
+        logger.log("Checking the light sensor array\r\n");
+
+        let light_sensor_array = &mut status.board.light_sensor_array;
+        let acquisition_mode = light_sensor_array.get_acquisition_mode();
+        light_sensor_array.set_acquisition_mode(AcquisitionMode::Direct);
+
+        light_sensor_array.set_led(false);
+        status.board.delay.delay_ms(SENSOR_SETTLING_MS);
+        let led_off = read_average_light_map(light_sensor_array, &mut status.board.delay);
+        light_sensor_array.set_led(true);
+        status.board.delay.delay_ms(SENSOR_SETTLING_MS);
+        let led_on = read_average_light_map(light_sensor_array, &mut status.board.delay);
+        light_sensor_array.set_led(false);
+
+        light_sensor_array.set_acquisition_mode(acquisition_mode);
+
+        let report = diagnose(&led_off, &led_on, &Default::default());
+        for (i, channel_status) in report.iter().enumerate() {
+            logger.log(" sensor ");
+            logger.log(&"01234567"[i..i + 1]);
+            logger.log(": off ");
+            logger.log_u16(&led_off[i]);
+            logger.log(", on ");
+            logger.log_u16(&led_on[i]);
+            logger.log(match channel_status {
+                ChannelStatus::Ok => " -> OK\r\n",
+                ChannelStatus::StuckLow => " -> FAIL (stuck low)\r\n",
+                ChannelStatus::StuckHigh => " -> FAIL (stuck high)\r\n",
+                ChannelStatus::NoEmitterResponse => " -> FAIL (no emitter response)\r\n",
+                ChannelStatus::InconsistentWithNeighbours => " -> WARN (differs from neighbours)\r\n",
+            });
+            if channel_status.is_dead() && dead_sensor.is_none() {
+                dead_sensor = Some(i);
+            }
         }
     }
 
     {
         logger.log("Hardware check done\r\n");
 
-        beep(&mut status.board.buzzer, &mut status.board.delay);
-        status.board.delay.delay_ms(50u32);
-        beep(&mut status.board.buzzer, &mut status.board.delay);
+        let buzzer = &mut status.board.buzzer;
+        let delay = &mut status.board.delay;
+        match dead_sensor {
+            None => status_sounds::play(buzzer, delay, Cue::HardwareCheckOk.notes()),
+            // The fault code of the first dead sensor
+            Some(sensor) => status_sounds::play(buzzer, delay, light_sensor_fault(sensor).notes()),
+        }
+
+        if let Some(sensor) = dead_sensor {
+            let pulses = Pattern::Pulses {
+                count: sensor as u8 + 1,
+            };
+            let now_ms = status.board.clock.now_ms();
+            status.leds.set(PairPattern::new(pulses, Pattern::Off), now_ms);
+            show_leds(
+                &mut status.leds,
+                &mut status.board.led_d1,
+                &mut status.board.led_d2,
+                &status.board.clock,
+                &mut status.board.delay,
+                pulses.period_ms(),
+            );
+        }
     }
 
     if status.board.battery_sensor.is_battery_low() {
-        logger.log("Battery is low\r\n");
-        FSMEvent::BatteryIsLow
+        error!(logger, "Battery is low");
+        FSMEvent::BatteryCritical
+    } else if dead_sensor.is_some() {
+        error!(logger, "Light sensor failure");
+        FSMEvent::SensorFailure
     } else {
         FSMEvent::NothingHappened
     }
 }
 
-fn beep(buzzer: &mut TimerBasedBuzzer, delay: &mut SysDelay) {
-    buzzer.turn_on();
-    buzzer.change_frequency(70, 1828);
-    delay.delay_ms(100u32);
-    buzzer.turn_off();
+// Time the leds blink for the user to check them
+const LED_CHECK_MS: u32 = 1000;
+
+// Run the patterns of the leds for `duration_ms`
+fn show_leds(
+    leds: &mut PairRunner,
+    led_d1: &mut Pin<'C', 13, Output>,
+    led_d2: &mut Pin<'B', 12, Output>,
+    clock: &Clock,
+    delay: &mut SysDelay,
+    duration_ms: u32,
+) {
+    let start_ms = clock.now_ms();
+    loop {
+        let now_ms = clock.now_ms();
+        if now_ms.wrapping_sub(start_ms) >= duration_ms {
+            break;
+        }
+        leds.tick(led_d1, led_d2, now_ms);
+        delay.delay_ms(1u32);
+    }
+}
+
+// Time for the sensors to follow the emitter LED changes
+const SENSOR_SETTLING_MS: u32 = 10;
+// Number of light maps averaged to remove the noise
+const SENSOR_SAMPLES: u32 = 8;
+
+fn read_average_light_map(
+    light_sensor_array: &mut impl LightSensorArrayController,
+    delay: &mut SysDelay,
+) -> [u16; 8] {
+    let mut sum = [0u32; 8];
+    for _ in 0..SENSOR_SAMPLES {
+        let light_map = light_sensor_array.get_light_map();
+        for (sum, value) in sum.iter_mut().zip(light_map) {
+            *sum += value as u32;
+        }
+        delay.delay_ms(1u32);
+    }
+    sum.map(|value| (value / SENSOR_SAMPLES) as u16)
 }
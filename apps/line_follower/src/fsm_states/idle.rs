@@ -6,44 +6,107 @@
 /// The state prints a menu to the user and waits for the following actions:
 ///  - button 1 pressed: go to hardware check state
 ///  - button 2 pressed: go to calibration state
+///  - buttons 1 and 3 pressed together: go to settings menu state
 ///  - battery is low: go to battery low state
+///  - battery is getting low: warn the user (with the battery warning cue) and show the menu again
+///
+/// The battery voltage and state of charge are shown with the menu, and again when 'b' is pressed.
+/// The battery voltage reading can be calibrated pressing 'v' and typing the actual battery voltage
+/// in millivolts (measured with a multimeter). The calibration is stored in the flash. The buttons
+/// and the battery level are still checked while the voltage is typed.
+/// Pressing 'h' shows the battery voltage history, the discharge rate and the estimated time left
+/// until the battery is critical. Pressing 'd' changes the level of the logs of all the states
+/// (warn, info, debug or trace), so the debug logs can be silenced without recompiling.
 ///
 /// During this state the led D1 is on and the led D2 is off.
 ///
 /// The state output events are:
 /// - Button1Pressed: When the user presses the button 1.
 /// - Button2Pressed: When the user presses the button 2.
-/// - BatteryIsLow: When the battery is low.
+/// - Buttons1And3Pressed: When the user presses the buttons 1 and 3 together.
+/// - BatteryWarning: When the battery level falls to warning.
+/// - BatteryCritical: When the battery is low.
+use battery_sensor_controller::monitor::BatteryMonitor;
 use battery_sensor_controller::BatterySensorController;
-use hal_button::ButtonController;
+use mightybuga_bsc::button_events::{BUTTON_1, BUTTON_2};
+use mightybuga_bsc::timer_based_buzzer::status_sounds::Cue;
 
 use crate::fsm::FSMEvent;
-use crate::line_follower_status::LineFollowerStatus;
+use crate::fsm_states::settings_menu::SETTINGS_MENU_CHORD;
+use crate::line_follower_status::{battery_event, LineFollowerStatus};
+use crate::status_sounds;
 
-use logging::Logger;
+use logging::{debug, Level, Logger};
 
 pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
-    let mut logger = Logger::new(&mut status.board.serial.tx);
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("idle")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     logger.log("Idle state\r\n");
-    status.board.led_d1.set_high();
-    status.board.led_d2.set_low();
+    // The presses of the previous state are not taken as new ones
+    status.board.button_events.clear();
 
     print_menu(&mut logger);
+    print_battery_status(&mut logger, &mut status.board.battery_sensor);
+
+    // The battery voltage being typed after pressing 'v'
+    let mut voltage_input: Option<NumberInput> = None;
 
     loop {
-        if status.board.btn_1.is_pressed() {
-            return FSMEvent::Button1Pressed;
-        }
+        let now_ms = status.board.clock.now_ms();
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
 
-        if status.board.btn_2.is_pressed() {
-            return FSMEvent::Button2Pressed;
+        // The buttons act when released, so the buttons 1 and 3 pressed together are not taken as
+        // a press of the button 1
+        match status.board.button_events.pop_chord() {
+            Some(BUTTON_1) => return FSMEvent::Button1Pressed,
+            Some(BUTTON_2) => return FSMEvent::Button2Pressed,
+            Some(SETTINGS_MENU_CHORD) => return FSMEvent::Buttons1And3Pressed,
+            _ => {}
         }
 
-        if status.board.battery_sensor.is_battery_low() {
-            return FSMEvent::BatteryIsLow;
+        let battery = battery_event(&mut status.board.battery_sensor, &mut status.battery, now_ms);
+        if let Some(event) = battery {
+            if let FSMEvent::BatteryWarning = event {
+                status_sounds::play(
+                    &mut status.board.buzzer,
+                    &mut status.board.delay,
+                    Cue::BatteryWarning.notes(),
+                );
+            }
+            return event;
         }
 
         if let Ok(byte) = status.board.serial.rx.read() {
+            if let Some(input) = voltage_input.as_mut() {
+                match input.push(byte) {
+                    NumberInputResult::Typing => {}
+                    NumberInputResult::Number(known_millivolts) => {
+                        voltage_input = None;
+                        match status.board.battery_sensor.calibrate(known_millivolts) {
+                            Some(factor) => {
+                                let mut settings = status.board.settings_storage.load_or_default();
+                                settings.battery_calibration_factor = factor;
+                                match status.board.settings_storage.store(&settings) {
+                                    Ok(()) => logger.log("\r\nBattery calibration stored\r\n"),
+                                    Err(_) => {
+                                        logger.log("\r\nCouldn't store the calibration\r\n")
+                                    }
+                                }
+                            }
+                            None => logger.log("\r\nThe voltage is too far from the reading\r\n"),
+                        }
+                        print_battery_status(&mut logger, &mut status.board.battery_sensor);
+                    }
+                    NumberInputResult::Invalid => {
+                        voltage_input = None;
+                        logger.log("\r\nInvalid voltage\r\n");
+                    }
+                }
+                continue;
+            }
+
             match byte {
                 b'1' => {
                     return FSMEvent::Button1Pressed;
@@ -51,11 +114,36 @@ pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
                 b'2' => {
                     return FSMEvent::Button2Pressed;
                 }
+                b's' => {
+                    return FSMEvent::Buttons1And3Pressed;
+                }
                 b'l' => {
-                    return FSMEvent::BatteryIsLow;
+                    return FSMEvent::BatteryCritical;
+                }
+                b'b' => {
+                    print_battery_status(&mut logger, &mut status.board.battery_sensor);
+                }
+                b'h' => {
+                    // The time left is estimated until the battery is critical
+                    let state_of_charge = &status.board.battery_sensor.state_of_charge;
+                    let critical_percent = state_of_charge.config.critical_percent;
+                    let cutoff_mv = state_of_charge.millivolts(critical_percent);
+                    print_battery_history(&mut logger, &status.battery.monitor, cutoff_mv);
+                }
+                b'v' => {
+                    logger.log("Type the battery voltage in millivolts and press enter: ");
+                    voltage_input = Some(NumberInput::default());
+                }
+                b'd' => {
+                    let level = next_log_level(status.log_filter.max_level());
+                    status.log_filter.set_max_level(level);
+                    logger.log("Log level: ");
+                    logger.log(level.name());
+                    logger.log("\r\n");
                 }
                 _ => {
-                    logger.log("Invalid input\r\n");
+                    // Shown only with the debug logs, the stray bytes are common
+                    debug!(logger, "Invalid input: {:#04x}", byte);
                 }
             }
         }
@@ -66,8 +154,101 @@ fn print_menu(logger: &mut Logger) {
     logger.log("Menu:\r\n");
     logger.log(" press button 1 to go to hardware check state\r\n");
     logger.log(" press button 2 to go to calibration state\r\n");
+    logger.log(" press buttons 1 and 3 together to go to settings menu state\r\n");
     logger.log(" you can also use the following keys:\r\n");
     logger.log(" press '1' to go to hardware check state\r\n");
     logger.log(" press '2' to go to calibration state\r\n");
+    logger.log(" press 's' to go to settings menu state\r\n");
     logger.log(" press 'l' to go to battery low state\r\n");
+    logger.log(" press 'b' to show the battery status\r\n");
+    logger.log(" press 'v' to calibrate the battery voltage\r\n");
+    logger.log(" press 'h' to show the battery history\r\n");
+    logger.log(" press 'd' to change the log level (warn, info, debug or trace)\r\n");
+}
+
+// The log levels that can be chosen, in order
+fn next_log_level(level: Level) -> Level {
+    match level {
+        Level::Warn => Level::Info,
+        Level::Info => Level::Debug,
+        Level::Debug => Level::Trace,
+        _ => Level::Warn,
+    }
+}
+
+fn print_battery_history<const N: usize>(
+    logger: &mut Logger,
+    monitor: &BatteryMonitor<N>,
+    cutoff_mv: u16,
+) {
+    logger.log("Battery history (s, mV):\r\n");
+    for sample in monitor.samples() {
+        logger.log_u16(&((sample.time_ms / 1000).min(u16::MAX as u32) as u16));
+        logger.log(" ");
+        logger.log_u16(&sample.millivolts);
+        logger.log("\r\n");
+    }
+
+    match monitor.discharge_rate() {
+        Some(rate) if rate > 0 => {
+            logger.log("Discharge rate: ");
+            logger.log_u16(&(rate.min(u16::MAX as i32) as u16));
+            logger.log(" mV/h\r\n");
+        }
+        _ => logger.log("The battery is not discharging\r\n"),
+    }
+    if let Some(remaining_ms) = monitor.remaining_ms(cutoff_mv) {
+        logger.log("Time left: ");
+        logger.log_u16(&((remaining_ms / 60_000).min(u16::MAX as u32) as u16));
+        logger.log(" min\r\n");
+    }
+}
+
+// A decimal number typed in the serial port, ended by a carriage return or a line feed. The bytes
+// are pushed one by one as they arrive, so the idle loop doesn't block while the number is typed.
+#[derive(Default)]
+struct NumberInput {
+    number: u16,
+    digits: u8,
+}
+
+enum NumberInputResult {
+    // More digits are expected
+    Typing,
+    Number(u16),
+    // Not a number, or too big for a u16
+    Invalid,
+}
+
+impl NumberInput {
+    fn push(&mut self, byte: u8) -> NumberInputResult {
+        match byte {
+            b'\r' | b'\n' if self.digits > 0 => NumberInputResult::Number(self.number),
+            b'0'..=b'9' => {
+                let number = self
+                    .number
+                    .checked_mul(10)
+                    .and_then(|number| number.checked_add((byte - b'0') as u16));
+                match number {
+                    Some(number) => {
+                        self.number = number;
+                        self.digits += 1;
+                        NumberInputResult::Typing
+                    }
+                    None => NumberInputResult::Invalid,
+                }
+            }
+            _ => NumberInputResult::Invalid,
+        }
+    }
+}
+
+fn print_battery_status(logger: &mut Logger, battery_sensor: &mut impl BatterySensorController) {
+    let millivolts = battery_sensor.get_battery_millivolts();
+    let percent = battery_sensor.get_state_of_charge();
+    logger.log("Battery: ");
+    logger.log_u16(&millivolts);
+    logger.log(" mV, ");
+    logger.log_u16(&(percent as u16));
+    logger.log(" %\r\n");
 }
@@ -3,44 +3,78 @@
 ///
 /// This state is responsible for following the line.
 ///
-/// Currently it does nothing, just logs the state, waits for a second and returns the proper event.
+/// When the line is lost, the robot keeps turning towards the side where the line was seen the last
+/// time. It only gives up after the line has not been seen for the line lost time of the settings.
+///
+/// The track markers (side marks and crossing lines) are logged, at the debug level, with the
+/// distance run by the robot when they were found.
+///
+/// The calibration is adapted during the run to follow the lighting changes of the track. The stored
+/// calibration is not modified, so every run starts from it.
 ///
 /// The state output events are:
 /// - Button2Pressed: When the user presses the button 2 (the user wants to end the state).
-/// - NothingHappend: When nothing happened, the line follower reaches a estate of end of following the line.
-/// - BatteryIsLow: When the battery is low.
+/// - LineLost: When the line has not been found again within the recovery budget.
+/// - BatteryCritical: When the battery is low. On a battery warning, the robot keeps following the
+///   line and plays the battery warning cue without blocking. While the motors run, the battery
+///   level is estimated compensating the voltage sag (critical at about 6.4 V).
+///
+/// The start countdown cue is played when the countdown starts, and the line lost cue when the
+/// robot gives up looking for the line.
+///
+/// The leds D1 and D2 blink alternating during the countdown, and then they show where the line is.
 use mightybuga_bsc::prelude::*;
+use mightybuga_bsc::button_events::ButtonId;
+use mightybuga_bsc::timer_based_buzzer::status_sounds::Cue;
+use mightybuga_bsc::EncoderController;
 
 use crate::fsm::FSMEvent;
-use crate::line_follower_status::LineFollowerStatus;
+use crate::line_follower_status::{battery_event, battery_event_under_load, LineFollowerStatus};
+use crate::line_lost_recovery::{LineLostRecovery, LineSide};
+use crate::status_sounds;
 
+use light_sensor_array_controller::adaptive_calibration::AdaptiveCalibration;
+use light_sensor_array_controller::calibration::CALIBRATED_MAX;
+use light_sensor_array_controller::line_position::LinePositionEstimator;
+use light_sensor_array_controller::marker::{MarkerDetector, MarkerDetectorConfig};
 use light_sensor_array_controller::LightSensorArrayController;
-use battery_sensor_controller::BatterySensorController;
 use engine::engine::EngineController;
-use hal_button::ButtonController;
-use logging::Logger;
+use logging::{debug, warn, Logger};
+
+// Encoder steps per meter run by a wheel: 240 steps per revolution (60 pulses, the 4 edges of every
+// pulse are counted) and a 32 mm wheel
+const ENCODER_STEPS_PER_METER: i64 = 2387;
 
 pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
-    let mut logger = Logger::new(&mut status.board.serial.tx);
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("line_following")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     logger.log("Line following state\r\n");
 
     // First, the line follower will wait for 5 seconds before starting to move. It will allow button 2 to be pressed to stop the line follower.
     // and monitor the battery level. Button 1 exits the loop.
     logger.log("Waiting for 5 seconds before starting to move\r\n");
+    // The buttons are read from the button events, so a press during the delays is not lost
+    status.board.button_events.clear();
+    status_sounds::play(
+        &mut status.board.buzzer,
+        &mut status.board.delay,
+        Cue::StartCountdown.notes(),
+    );
     for _ in 0..90 {
-        status.board.led_d1.toggle();
-        status.board.led_d2.toggle();
+        let now_ms = status.board.clock.now_ms();
+        status.leds.tick(&mut status.board.led_d1, &mut status.board.led_d2, now_ms);
         status.board.delay.delay_ms(50u32);
 
-        if status.board.btn_1.is_pressed() {
-            break;
-        }
-
-        if status.board.btn_2.is_pressed() {
-            return FSMEvent::Button2Pressed;
+        match status.board.button_events.pop_press() {
+            Some(ButtonId::Button1) => break,
+            Some(ButtonId::Button2) => return FSMEvent::Button2Pressed,
+            _ => {}
         }
-        if status.board.battery_sensor.is_battery_low() {
-            return FSMEvent::BatteryIsLow;
+        let battery = battery_event(&mut status.board.battery_sensor, &mut status.battery, now_ms);
+        if let Some(FSMEvent::BatteryCritical) = battery {
+            return FSMEvent::BatteryCritical;
         }
         if let Ok(serial_input) = status.board.serial.rx.read() {
             match serial_input {
@@ -61,16 +95,54 @@ pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
     // - If the line is in the middle of the sensors, it will move forward.
     // - If the line is on the left sensors, it will turn left.
     // - If the line is on the right sensors, it will turn right.
-    // - If there is no line, it will stop.
+    // - If there is no line, it will turn towards the side where the line was last seen, and stop
+    //   if the line is not found within the recovery budget.
     // - If the button 2 is pressed, it will stop.
     // - If the battery is low, it will stop.
+    // The speed and the time looking for the line are set with the settings menu
+    let speed_profile = status.settings.speed_profile;
+    let (duty, delta) = (speed_profile.duty(), speed_profile.delta());
+    let recovery_delta = speed_profile.recovery_delta();
+    let mut recovery = LineLostRecovery::new(status.settings.line_lost_time_ms());
+    let estimator = LinePositionEstimator::with_calibration(&status.calibration);
+    let mut calibration = AdaptiveCalibration::new(status.calibration, Default::default());
+    let mut markers = MarkerDetector::new(MarkerDetectorConfig {
+        dark_threshold: CALIBRATED_MAX / 2,
+        ..Default::default()
+    });
+
+    // The odometer adds the steps moved by both wheels (the distance is their average), it goes
+    // back when the robot reverses
+    let mut odometer_steps: i64 = 0;
+    status.board.encoder_l.enable();
+    status.board.encoder_r.enable();
+    status.board.encoder_l.delta();
+    status.board.encoder_r.delta();
+
     loop {
+        let (delta_l, _) = status.board.encoder_l.delta();
+        let (delta_r, _) = status.board.encoder_r.delta();
+        odometer_steps += (delta_l + delta_r) as i64;
+
         let line_sensor = status.board.light_sensor_array.get_light_map();
-        let line_position = get_line_position(line_sensor);
+        let normalized_line_sensor = calibration.normalize(&line_sensor);
+        calibration.update(&line_sensor, estimator.estimate(&normalized_line_sensor).as_ref());
+        let line_position = estimator.get_line_position(&normalized_line_sensor);
+
+        // The detector takes the differences of the distances, so it can wrap
+        let distance_mm = (odometer_steps * 1000 / (2 * ENCODER_STEPS_PER_METER)) as u32;
+        if let Some(marker) = markers.update(&normalized_line_sensor, distance_mm) {
+            debug!(
+                logger,
+                "Marker {:?} at {} mm, {} mm long",
+                marker.kind,
+                marker.start_mm,
+                marker.length_mm
+            );
+        }
         match line_position {
             Some(position) => {
-                let duty = 15000;
-                let delta = 2000;
+                recovery.line_seen(position);
 
                 if position == 0. {
                     status.board.led_d1.set_high();
@@ -85,24 +157,48 @@ pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
                     status.board.led_d2.set_low();
                     status.board.engine.right(duty, delta);
                 }
-                
             }
             None => {
-                logger.log("No line detected\r\n");
-                turn_off_robot(status);
-                return FSMEvent::NothingHappened;
+                if !recovery.is_recovering() {
+                    debug!(logger, "No line detected, looking for it");
+                }
+                match recovery.line_lost(status.board.clock.now_ms()) {
+                    Some(LineSide::Left) => status.board.engine.left(duty, recovery_delta),
+                    Some(LineSide::Right) => status.board.engine.right(duty, recovery_delta),
+                    Some(LineSide::Center) => status.board.engine.forward(duty),
+                    None => {
+                        warn!(logger, "Line lost");
+                        turn_off_robot(status);
+                        status_sounds::play(
+                            &mut status.board.buzzer,
+                            &mut status.board.delay,
+                            Cue::LineLost.notes(),
+                        );
+                        return FSMEvent::LineLost;
+                    }
+                }
             }
         }
 
         status.board.delay.delay_ms(50u32);
+        let now_ms = status.board.clock.now_ms();
+        status.melody_player.tick(&mut status.board.buzzer, now_ms);
 
-        if status.board.btn_2.is_pressed() {
+        if let Some(ButtonId::Button2) = status.board.button_events.pop_press() {
             turn_off_robot(status);
             return FSMEvent::Button2Pressed;
         }
-        if status.board.battery_sensor.is_battery_low() {
-            turn_off_robot(status);
-            return FSMEvent::BatteryIsLow;
+        match battery_event_under_load(&mut status.board.battery_sensor, &mut status.battery) {
+            Some(FSMEvent::BatteryCritical) => {
+                turn_off_robot(status);
+                return FSMEvent::BatteryCritical;
+            }
+            Some(FSMEvent::BatteryWarning) => {
+                warn!(logger, "Battery is getting low");
+                let melody = Cue::BatteryWarning.melody();
+                status.melody_player.play(&mut status.board.buzzer, melody, now_ms);
+            }
+            _ => {}
         }
         if let Ok(serial_input) = status.board.serial.rx.read() {
             match serial_input {
@@ -118,39 +214,8 @@ pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
 
 fn turn_off_robot(status: &mut LineFollowerStatus) {
     status.board.engine.stop();
+    status.melody_player.stop(&mut status.board.buzzer);
     status.board.led_d1.set_low();
     status.board.led_d2.set_low();
     status.board.light_sensor_array.set_led(false);
 }
-
-// get_line_position function returns a value between -1 and 1, where -1 means the line is on the left, 0 means the line is in the middle, and 1 means the line is on the right.
-// The line sensor is an array of 8 values, where each value represents the light intensity of a sensor. The higher the value, the less light is detected (the line is black).
-// The function calculates the position of the line as the position of the sensor with the lowest value.
-// If no line is detected, the function returns None.
-fn get_line_position(line_sensor: [u16; 8]) -> Option<f32> {
-    let mut max_value = line_sensor[0];
-    let mut max_index = 0;
-    for i in 1..8 {
-        if line_sensor[i] > max_value {
-            max_value = line_sensor[i];
-            max_index = i;
-        }
-    }
-
-    let max_threshold = 1000;
-    if max_value < max_threshold {
-        return None;
-    }
-
-    match max_index {
-        0 => Some(1.),   // line is on the right
-        1 => Some(0.75),
-        2 => Some(0.5),
-        3 => Some(0.0),
-        4 => Some(0.0),
-        5 => Some(-0.5),
-        6 => Some(-0.75),
-        7 => Some(-1.),  // line is on the left
-        _ => None,
-    }
-}
@@ -3,3 +3,4 @@ pub mod calibration;
 pub mod hardware_check;
 pub mod idle;
 pub mod line_following;
+pub mod settings_menu;
@@ -0,0 +1,225 @@
+/// Settings menu state
+///
+/// This state lets the user change the run settings at the track, without a laptop, using the
+/// three buttons of the robot. It is entered from the idle state pressing the buttons 1 and 3
+/// together.
+///
+/// The menu has these items:
+///  1. Speed profile: slow, normal or fast.
+///  2. Line lost time: how long the robot looks for the line before giving up (250, 500 or 1000 ms).
+///  3. Start calibration.
+///  4. Volume of the buzzer: 100, 50, 20 or 0 % (silent).
+///
+/// The buttons are used as follows:
+///  - button 1: go to the next item. The buzzer beeps the number of the item (low tone) and the leds
+///    show it in binary (D1 is the bit 0, D2 is the bit 1, so both are off for the item 4).
+///  - button 3: change the value of the item to the next one, and the buzzer beeps the number of
+///    the value (high tone). On "start calibration", go to the calibration state.
+///  - buttons 2 and 3 together: restore the default settings (a long beep).
+///  - button 2: go back to the idle state.
+///
+/// The keys '1', '2' and '3' of the serial port can be used as the buttons, and 'r' restores the
+/// default settings. The settings are kept until the robot is powered off.
+///
+/// The state output events are:
+/// - Button2Pressed: When the user presses the button 2 (the user wants to exit the menu).
+/// - CalibrationSelected: When the user selects the start calibration item.
+/// - BatteryCritical: When the battery is low.
+use crate::board::timer::SysDelay;
+use hal_button::Chord;
+use mightybuga_bsc::button_events::{ButtonId, BUTTON_1, BUTTON_2, BUTTON_3};
+use mightybuga_bsc::gpio::{Output, Pin};
+use mightybuga_bsc::timer_based_buzzer::melody::Note;
+use mightybuga_bsc::timer_based_buzzer::pitch::{midi_note, NoteName};
+use mightybuga_bsc::timer_based_buzzer::status_sounds::beeps;
+use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
+use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzerInterface;
+
+use crate::fsm::FSMEvent;
+use crate::line_follower_status::{battery_event, LineFollowerStatus};
+use crate::run_settings::RunSettings;
+use crate::status_sounds;
+
+use logging::Logger;
+
+/// Chord to enter the settings menu from the idle state
+pub const SETTINGS_MENU_CHORD: Chord =
+    Chord::of(&[ButtonId::Button1.index(), ButtonId::Button3.index()]);
+// Chord to restore the default settings
+const RESTORE_DEFAULTS_CHORD: Chord =
+    Chord::of(&[ButtonId::Button2.index(), ButtonId::Button3.index()]);
+
+// Notes of the beeps: the items use a low tone and the values a high one
+const ITEM_TONE: u8 = midi_note(NoteName::C, 5);
+const VALUE_TONE: u8 = midi_note(NoteName::F, 5);
+
+#[derive(Clone, Copy)]
+enum MenuItem {
+    SpeedProfile,
+    LineLostTime,
+    StartCalibration,
+    Volume,
+}
+
+impl MenuItem {
+    fn next(self) -> Self {
+        match self {
+            MenuItem::SpeedProfile => MenuItem::LineLostTime,
+            MenuItem::LineLostTime => MenuItem::StartCalibration,
+            MenuItem::StartCalibration => MenuItem::Volume,
+            MenuItem::Volume => MenuItem::SpeedProfile,
+        }
+    }
+
+    fn number(self) -> u8 {
+        self as u8 + 1
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            MenuItem::SpeedProfile => "speed profile",
+            MenuItem::LineLostTime => "line lost time",
+            MenuItem::StartCalibration => "start calibration",
+            MenuItem::Volume => "volume",
+        }
+    }
+}
+
+pub fn run(status: &mut LineFollowerStatus) -> FSMEvent {
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("settings_menu")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
+    logger.log("Settings menu state\r\n");
+    logger.log(" button 1: next item, button 3: change the value, button 2: exit\r\n");
+    logger.log(" buttons 2 and 3: restore the default settings\r\n");
+    // The chord that opened the menu is not taken as a menu action
+    status.board.button_events.clear();
+
+    let mut item = MenuItem::SpeedProfile;
+    log_item(&mut logger, item);
+    show_item(
+        &mut status.board.led_d1,
+        &mut status.board.led_d2,
+        &mut status.board.buzzer,
+        &mut status.board.delay,
+        item,
+    );
+
+    loop {
+        let mut chord = status.board.button_events.pop_chord();
+        if let Ok(serial_input) = status.board.serial.rx.read() {
+            chord = match serial_input {
+                b'1' => Some(BUTTON_1),
+                b'2' => Some(BUTTON_2),
+                b'3' => Some(BUTTON_3),
+                b'r' => Some(RESTORE_DEFAULTS_CHORD),
+                _ => chord,
+            };
+        }
+
+        match chord {
+            Some(BUTTON_1) => {
+                item = item.next();
+                log_item(&mut logger, item);
+                show_item(
+                    &mut status.board.led_d1,
+                    &mut status.board.led_d2,
+                    &mut status.board.buzzer,
+                    &mut status.board.delay,
+                    item,
+                );
+            }
+            Some(BUTTON_2) => {
+                logger.log("Exit settings menu\r\n");
+                return FSMEvent::Button2Pressed;
+            }
+            Some(BUTTON_3) => {
+                let value = match item {
+                    MenuItem::SpeedProfile => {
+                        let speed_profile = status.settings.speed_profile.next();
+                        status.settings.speed_profile = speed_profile;
+                        logger.log("Speed profile: ");
+                        logger.log(speed_profile.name());
+                        logger.log("\r\n");
+                        speed_profile.number()
+                    }
+                    MenuItem::LineLostTime => {
+                        status.settings.next_line_lost_time();
+                        logger.log("Line lost time (ms): ");
+                        logger.log_u16(&(status.settings.line_lost_time_ms() as u16));
+                        logger.log("\r\n");
+                        status.settings.line_lost_time_number()
+                    }
+                    MenuItem::StartCalibration => return FSMEvent::CalibrationSelected,
+                    MenuItem::Volume => {
+                        status.settings.next_volume();
+                        let volume = status.settings.volume_percent();
+                        // The beeps of the value are already played with the new volume
+                        status.board.buzzer.set_volume(volume);
+                        logger.log("Volume (%): ");
+                        logger.log_u16(&(volume as u16));
+                        logger.log("\r\n");
+                        status.settings.volume_number()
+                    }
+                };
+                status_sounds::play(
+                    &mut status.board.buzzer,
+                    &mut status.board.delay,
+                    beeps(VALUE_TONE, value),
+                );
+            }
+            Some(RESTORE_DEFAULTS_CHORD) => {
+                status.settings = RunSettings::default();
+                let volume = status.settings.volume_percent();
+                status.board.buzzer.set_volume(volume);
+                logger.log("Default settings restored\r\n");
+                status_sounds::play(
+                    &mut status.board.buzzer,
+                    &mut status.board.delay,
+                    [Note::tone(VALUE_TONE, 600)],
+                );
+            }
+            _ => {}
+        }
+
+        let battery = battery_event(
+            &mut status.board.battery_sensor,
+            &mut status.battery,
+            status.board.clock.now_ms(),
+        );
+        if let Some(FSMEvent::BatteryCritical) = battery {
+            return FSMEvent::BatteryCritical;
+        }
+    }
+}
+
+fn log_item(logger: &mut Logger, item: MenuItem) {
+    logger.log("Item ");
+    logger.log_u16(&(item.number() as u16));
+    logger.log(": ");
+    logger.log(item.name());
+    logger.log("\r\n");
+}
+
+// Show the number of the item with the leds and beep it
+fn show_item(
+    led_d1: &mut Pin<'C', 13, Output>,
+    led_d2: &mut Pin<'B', 12, Output>,
+    buzzer: &mut TimerBasedBuzzer,
+    delay: &mut SysDelay,
+    item: MenuItem,
+) {
+    let number = item.number();
+    if number & 0b01 != 0 {
+        led_d1.set_high();
+    } else {
+        led_d1.set_low();
+    }
+    if number & 0b10 != 0 {
+        led_d2.set_high();
+    } else {
+        led_d2.set_low();
+    }
+    status_sounds::play(buzzer, delay, beeps(ITEM_TONE, number));
+}
@@ -1,6 +1,107 @@
 use crate::board;
+use crate::fsm::FSMEvent;
+use crate::run_settings::RunSettings;
+use battery_sensor_controller::monitor::BatteryMonitor;
+use battery_sensor_controller::state_of_charge::BatteryLevel;
+use battery_sensor_controller::BatterySensorController;
+use led_patterns::PairRunner;
+use light_sensor_array_controller::calibration::Calibration;
+use logging::{LogClock, LogFilter};
+use mightybuga_bsc::clock::Clock;
+use mightybuga_bsc::timer_based_buzzer::melody::MelodyPlayer;
 
 // Line follower state shared between the different states
 pub struct LineFollowerStatus {
+    // The states log through a Logger that borrows `board.serial.tx` while they run, so the helpers
+    // they call take the fields they use instead of the whole status
     pub board: board::Mightybuga_BSC,
+    // Calibration of the light sensor array, including the polarity of the line
+    pub calibration: Calibration,
+    pub battery: BatteryStatus,
+    // A light sensor was found dead by the last hardware check, the line following is not started
+    // until a hardware check passes
+    pub light_sensor_failure: bool,
+    // Settings of the line following, changed with the settings menu
+    pub settings: RunSettings,
+    // Melodies played with the buzzer while the states keep working. The states that use it must
+    // tick it from their loops.
+    pub melody_player: MelodyPlayer<MELODY_QUEUE_LEN>,
+    // Patterns of the leds D1 (first) and D2 (second). The pattern of every state is set when the
+    // state starts (see FSMState::led_pattern), and the states must tick it from their loops.
+    pub leds: PairRunner,
+    // Filter of the logs of all the states, it can be changed at runtime
+    pub log_filter: LogFilter,
+    // Clock of the timestamps of the logs
+    pub log_clock: LogTimestamps,
+}
+
+// The board clock, to timestamp the logs. The BSC doesn't depend on the logging library (it needs
+// an allocator), so the clock is wrapped here.
+#[derive(Clone, Copy)]
+pub struct LogTimestamps(pub Clock);
+
+impl LogClock for LogTimestamps {
+    fn now_ms(&self) -> u32 {
+        self.0.now_ms()
+    }
+}
+
+// Melodies that can be queued in the melody player
+pub const MELODY_QUEUE_LEN: usize = 4;
+
+// Samples kept in the battery history, one every BATTERY_SAMPLE_PERIOD_MS (about 10 minutes)
+pub const BATTERY_HISTORY_LEN: usize = 60;
+pub const BATTERY_SAMPLE_PERIOD_MS: u32 = 10_000;
+
+pub struct BatteryStatus {
+    // Last battery level seen, so the battery warning is only given once
+    pub level: BatteryLevel,
+    // History of the battery voltage, to estimate the remaining run time
+    pub monitor: BatteryMonitor<BATTERY_HISTORY_LEN>,
+}
+
+impl BatteryStatus {
+    pub fn new() -> Self {
+        BatteryStatus {
+            level: BatteryLevel::Ok,
+            monitor: BatteryMonitor::new(BATTERY_SAMPLE_PERIOD_MS),
+        }
+    }
+}
+
+// Check the battery level, updating the last level seen and the battery history. It gives
+// BatteryCritical while the battery is critical, and BatteryWarning only when the battery level
+// falls to warning.
+// The level is estimated from the resting voltage, so it must only be called while the motors are
+// stopped (see battery_event_under_load).
+pub fn battery_event(
+    battery_sensor: &mut impl BatterySensorController,
+    battery: &mut BatteryStatus,
+    now_ms: u32,
+) -> Option<FSMEvent> {
+    battery.monitor.update(battery_sensor, now_ms);
+    let level = battery_sensor.get_battery_level();
+    level_event(battery, level)
+}
+
+// Check the battery level while the motors run, as battery_event. The sagging voltages are not
+// recorded in the battery history, they would make the discharge rate look faster.
+pub fn battery_event_under_load(
+    battery_sensor: &mut impl BatterySensorController,
+    battery: &mut BatteryStatus,
+) -> Option<FSMEvent> {
+    let level = battery_sensor.get_battery_level_under_load();
+    level_event(battery, level)
+}
+
+fn level_event(battery: &mut BatteryStatus, level: BatteryLevel) -> Option<FSMEvent> {
+    let previous_level = battery.level;
+    battery.level = level;
+    match level {
+        BatteryLevel::Critical => Some(FSMEvent::BatteryCritical),
+        BatteryLevel::Warning if previous_level == BatteryLevel::Ok => {
+            Some(FSMEvent::BatteryWarning)
+        }
+        _ => None,
+    }
 }
@@ -0,0 +1,60 @@
+// Line lost recovery
+//
+// When the line is not seen (a noisy read, a sharp curve where the line goes out of the sensor
+// array or a gap in the line), the robot should not stop immediately. Instead, it remembers the
+// side where the line was last seen and keeps turning towards it until the line is found again or
+// the recovery budget (the time without seeing the line) runs out.
+
+// Side of the robot where the line was seen the last time
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineSide {
+    Left,
+    Center,
+    Right,
+}
+
+pub struct LineLostRecovery {
+    budget_ms: u32,
+    last_side: LineSide,
+    // time when the line was lost
+    lost_ms: Option<u32>,
+}
+
+impl LineLostRecovery {
+    // Give up after `budget_ms` without seeing the line
+    pub fn new(budget_ms: u32) -> Self {
+        LineLostRecovery {
+            budget_ms,
+            last_side: LineSide::Center,
+            lost_ms: None,
+        }
+    }
+
+    // The line has been seen at `position` (from -1 left to 1 right)
+    pub fn line_seen(&mut self, position: f32) {
+        self.last_side = if position < 0. {
+            LineSide::Left
+        } else if position > 0. {
+            LineSide::Right
+        } else {
+            LineSide::Center
+        };
+        self.lost_ms = None;
+    }
+
+    // True if the line is not being seen
+    pub fn is_recovering(&self) -> bool {
+        self.lost_ms.is_some()
+    }
+
+    // The line has not been seen. It returns the side to turn to in order to find the line, or
+    // None if the budget is over and the robot should give up.
+    pub fn line_lost(&mut self, now_ms: u32) -> Option<LineSide> {
+        let lost_ms = *self.lost_ms.get_or_insert(now_ms);
+
+        match now_ms.wrapping_sub(lost_ms) > self.budget_ms {
+            true => None,
+            false => Some(self.last_side),
+        }
+    }
+}
@@ -6,7 +6,7 @@
 use panic_probe as _;
 
 use mightybuga_bsc::prelude::*;
-use mightybuga_bsc::{self as board, Mightybuga_BSC};
+use mightybuga_bsc as board;
 use mightybuga_bsc::defmt; // for logging through the semihosting interface
 
 extern crate alloc;
@@ -18,49 +18,98 @@ static HEAP: Heap = Heap::empty();
 
 // This is not the defmt logger, but a simple one
 // that uses the serial interface to log messages.
-use logging::Logger;
+use logging::{error, info, warn, Logger};
 
 mod fsm;
 use fsm::{FSMEvent, FSMState};
 mod fsm_states;
 
 mod line_follower_status;
-use line_follower_status::LineFollowerStatus;
+use line_follower_status::{BatteryStatus, LineFollowerStatus, LogTimestamps};
+use mightybuga_bsc::timer_based_buzzer::melody::MelodyPlayer;
+use led_patterns::PairRunner;
+
+mod line_lost_recovery;
+
+mod run_settings;
+
+mod status_sounds;
+use mightybuga_bsc::timer_based_buzzer::status_sounds::Cue;
 
 #[entry]
 fn main() -> ! {
     let board = board::Mightybuga_BSC::take().unwrap();
+    let log_clock = LogTimestamps(board.clock);
 
-    let mut line_follower_status = LineFollowerStatus { board };
+    let mut line_follower_status = LineFollowerStatus {
+        board,
+        calibration: Default::default(),
+        battery: BatteryStatus::new(),
+        light_sensor_failure: false,
+        settings: Default::default(),
+        melody_player: MelodyPlayer::new(),
+        leds: PairRunner::new(),
+        log_filter: Default::default(),
+        log_clock,
+    };
+
+    status_sounds::play(
+        &mut line_follower_status.board.buzzer,
+        &mut line_follower_status.board.delay,
+        Cue::BootOk.notes(),
+    );
 
     let mut fsm_state = FSMState::Idle {};
     let mut fsm_event;
 
     loop {
         fsm_event = fsm_state.run(&mut line_follower_status);
-        log_event(&mut line_follower_status.board, fsm_event);
+        log_event(&mut line_follower_status, fsm_event);
         fsm_state = fsm_state.next(fsm_event, &mut line_follower_status);
     }
 }
 
-fn log_event(board: &mut Mightybuga_BSC, event: FSMEvent) {
-    let mut logger = Logger::new(&mut board.serial.tx);
+fn log_event(status: &mut LineFollowerStatus, event: FSMEvent) {
+    let mut logger = Logger::new(&mut status.board.serial.tx)
+        .tagged("fsm")
+        .filtered(&status.log_filter)
+        .timestamped(&status.log_clock);
     match event {
         FSMEvent::NothingHappened => {
-            logger.log(" - Nothing happened -\r\n");
+            info!(logger, "Nothing happened");
             defmt::info!(" - Nothing happened -\r\n");
         }
         FSMEvent::Button1Pressed => {
-            logger.log(" - Button 1 pressed -\r\n");
+            info!(logger, "Button 1 pressed");
             defmt::info!(" - Button 1 pressed -\r\n");
         }
         FSMEvent::Button2Pressed => {
-            logger.log(" - Button 2 pressed -\r\n");
+            info!(logger, "Button 2 pressed");
             defmt::info!(" - Button 2 pressed -\r\n");
         }
-        FSMEvent::BatteryIsLow => {
-            logger.log(" - Battery is low -\r\n");
-            defmt::warn!(" - Battery is low -\r\n");
+        FSMEvent::Buttons1And3Pressed => {
+            info!(logger, "Buttons 1 and 3 pressed");
+            defmt::info!(" - Buttons 1 and 3 pressed -\r\n");
+        }
+        FSMEvent::CalibrationSelected => {
+            info!(logger, "Calibration selected");
+            defmt::info!(" - Calibration selected -\r\n");
+        }
+        FSMEvent::BatteryWarning => {
+            warn!(logger, "Battery is getting low");
+            defmt::warn!(" - Battery is getting low -\r\n");
+        }
+        FSMEvent::BatteryCritical => {
+            error!(logger, "Battery is low");
+            defmt::error!(" - Battery is low -\r\n");
+        }
+        FSMEvent::LineLost => {
+            warn!(logger, "Line lost");
+            defmt::warn!(" - Line lost -\r\n");
+        }
+        FSMEvent::SensorFailure => {
+            error!(logger, "Light sensor failure");
+            defmt::error!(" - Light sensor failure -\r\n");
         }
     }
 }
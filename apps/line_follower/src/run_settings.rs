@@ -0,0 +1,115 @@
+// Run settings
+//
+// The settings of the line following that can be changed at the track with the on-robot settings
+// menu (see the settings menu state). Every setting has a small list of values, and the menu moves
+// to the next one of the list, going back to the first one after the last one.
+
+// Speed of the robot following the line
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpeedProfile {
+    Slow,
+    Normal,
+    Fast,
+}
+
+impl SpeedProfile {
+    pub fn next(self) -> Self {
+        match self {
+            SpeedProfile::Slow => SpeedProfile::Normal,
+            SpeedProfile::Normal => SpeedProfile::Fast,
+            SpeedProfile::Fast => SpeedProfile::Slow,
+        }
+    }
+
+    // Position of the profile in the list, starting at 1 (used for the beeps of the menu)
+    pub fn number(self) -> u8 {
+        self as u8 + 1
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SpeedProfile::Slow => "slow",
+            SpeedProfile::Normal => "normal",
+            SpeedProfile::Fast => "fast",
+        }
+    }
+
+    // Duty used to follow the line
+    pub fn duty(self) -> u16 {
+        match self {
+            SpeedProfile::Slow => 12000,
+            SpeedProfile::Normal => 15000,
+            SpeedProfile::Fast => 20000,
+        }
+    }
+
+    // Turning delta used to follow the line
+    pub fn delta(self) -> u16 {
+        match self {
+            SpeedProfile::Slow => 1600,
+            SpeedProfile::Normal => 2000,
+            SpeedProfile::Fast => 2700,
+        }
+    }
+
+    // Turning delta used to look for the line after losing it
+    pub fn recovery_delta(self) -> u16 {
+        match self {
+            SpeedProfile::Slow => 5000,
+            SpeedProfile::Normal => 6000,
+            SpeedProfile::Fast => 8000,
+        }
+    }
+}
+
+// Time looking for the line before giving up
+const LINE_LOST_TIMES_MS: [u32; 3] = [250, 500, 1000];
+// Volume of the buzzer, in percent (0 is silent)
+const VOLUMES_PERCENT: [u8; 4] = [100, 50, 20, 0];
+
+#[derive(Clone, Copy)]
+pub struct RunSettings {
+    pub speed_profile: SpeedProfile,
+    // Index in LINE_LOST_TIMES_MS
+    line_lost_time: usize,
+    // Index in VOLUMES_PERCENT
+    volume: usize,
+}
+
+impl Default for RunSettings {
+    fn default() -> Self {
+        RunSettings {
+            speed_profile: SpeedProfile::Normal,
+            line_lost_time: 1,
+            volume: 0,
+        }
+    }
+}
+
+impl RunSettings {
+    pub fn line_lost_time_ms(&self) -> u32 {
+        LINE_LOST_TIMES_MS[self.line_lost_time]
+    }
+
+    // Position of the line lost time in the list, starting at 1
+    pub fn line_lost_time_number(&self) -> u8 {
+        self.line_lost_time as u8 + 1
+    }
+
+    pub fn next_line_lost_time(&mut self) {
+        self.line_lost_time = (self.line_lost_time + 1) % LINE_LOST_TIMES_MS.len();
+    }
+
+    pub fn volume_percent(&self) -> u8 {
+        VOLUMES_PERCENT[self.volume]
+    }
+
+    // Position of the volume in the list, starting at 1
+    pub fn volume_number(&self) -> u8 {
+        self.volume as u8 + 1
+    }
+
+    pub fn next_volume(&mut self) {
+        self.volume = (self.volume + 1) % VOLUMES_PERCENT.len();
+    }
+}
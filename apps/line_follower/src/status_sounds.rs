@@ -0,0 +1,29 @@
+// Status sounds of the line follower
+//
+// The states use the cues and the fault codes of the buzzer status sounds, so the user can follow
+// the robot at the track without a serial connection. The fault codes of the line follower are:
+//  - 1-n: the light sensor n (1 to 8) is dead, found by the hardware check.
+//  - 2-1: the calibration failed, some sensor has not seen enough contrast.
+
+use crate::board::timer::SysDelay;
+use mightybuga_bsc::prelude::*;
+use mightybuga_bsc::timer_based_buzzer::melody::Note;
+use mightybuga_bsc::timer_based_buzzer::status_sounds::{self, FaultCode};
+use mightybuga_bsc::timer_based_buzzer::TimerBasedBuzzer;
+
+const LIGHT_SENSOR_FAULT_GROUP: u8 = 1;
+pub const CALIBRATION_FAULT: FaultCode = FaultCode::new(2, 1);
+
+// Fault code of the dead light sensor `sensor` (from 0 to 7)
+pub fn light_sensor_fault(sensor: usize) -> FaultCode {
+    FaultCode::new(LIGHT_SENSOR_FAULT_GROUP, sensor as u8 + 1)
+}
+
+// Play the notes of a cue or a fault code, blocking until they end
+pub fn play(
+    buzzer: &mut TimerBasedBuzzer,
+    delay: &mut SysDelay,
+    notes: impl IntoIterator<Item = Note>,
+) {
+    status_sounds::play_blocking(buzzer, notes, |ms| delay.delay_ms(ms));
+}
@@ -0,0 +1,156 @@
+//! Conversion of the ADC readings of the battery voltage divider to millivolts.
+//!
+//! The ADC measures relative to its supply (VDDA), that is not exactly 3.3 V and changes with the
+//! load of the regulator, so the internal reference (VREFINT, 1.2 V) is measured too and used to
+//! know the actual supply voltage. The tolerance of the resistors of the divider is compensated with
+//! a per robot calibration factor, set measuring a known battery voltage.
+
+/// The calibration factor that does not change the readings
+pub const UNIT_CALIBRATION_FACTOR: u32 = 10_000;
+
+/// How far (in percent) the calibration factor can be from `UNIT_CALIBRATION_FACTOR`. The
+/// tolerance of the divider is much lower, a factor out of this band comes from a wrong voltage
+/// (e.g. 74 typed instead of 7400) and would make all the readings wrong.
+pub const MAX_CALIBRATION_DEVIATION_PERCENT: u32 = 20;
+
+/// True if the calibration factor is within `MAX_CALIBRATION_DEVIATION_PERCENT` of
+/// `UNIT_CALIBRATION_FACTOR`
+pub fn is_valid_calibration_factor(factor: u32) -> bool {
+    factor.abs_diff(UNIT_CALIBRATION_FACTOR)
+        <= UNIT_CALIBRATION_FACTOR * MAX_CALIBRATION_DEVIATION_PERCENT / 100
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AdcConversion {
+    /// The voltage of the internal reference of the ADC
+    pub vrefint_millivolts: u32,
+    /// The maximum value given by the ADC
+    pub full_scale: u32,
+    /// The ratio between the battery voltage and the voltage at the ADC pin, multiplied by 1000
+    pub divider_ratio_by_1000: u32,
+    /// Correction of the divider ratio of this robot, `UNIT_CALIBRATION_FACTOR` is 1
+    pub calibration_factor: u32,
+}
+
+impl Default for AdcConversion {
+    /// The conversion of the MightyBugA: a 12 bits ADC and a 47k/20k divider
+    fn default() -> Self {
+        AdcConversion {
+            vrefint_millivolts: 1200,
+            full_scale: 4095,
+            divider_ratio_by_1000: 3350,
+            calibration_factor: UNIT_CALIBRATION_FACTOR,
+        }
+    }
+}
+
+impl AdcConversion {
+    /// Get the battery voltage from the raw reading of the divider and the raw reading of VREFINT
+    /// (both taken with the same ADC and the same number of samples, so they can be sums)
+    pub fn battery_millivolts(&self, raw_battery: u32, raw_vrefint: u32) -> u16 {
+        if raw_vrefint == 0 {
+            return 0;
+        }
+        // raw_battery / raw_vrefint is the pin voltage in VREFINT units
+        let pin_microvolts =
+            raw_battery as u64 * self.vrefint_millivolts as u64 * 1000 / raw_vrefint as u64;
+        let battery_millivolts =
+            pin_microvolts * self.divider_ratio_by_1000 as u64 * self.calibration_factor as u64
+                / (1000 * 1000 * UNIT_CALIBRATION_FACTOR as u64);
+        battery_millivolts.min(u16::MAX as u64) as u16
+    }
+
+    /// Get the supply voltage of the ADC from a raw reading of VREFINT
+    pub fn supply_millivolts(&self, raw_vrefint: u16) -> u16 {
+        if raw_vrefint == 0 {
+            return 0;
+        }
+        (self.vrefint_millivolts * self.full_scale / raw_vrefint as u32).min(u16::MAX as u32) as u16
+    }
+
+    /// Adjust the calibration factor, so a battery measured as `measured_millivolts` with the
+    /// current factor reads `known_millivolts` (e.g. measured with a multimeter). It returns false,
+    /// and the factor is not changed, if there is no measure or the new factor is not valid (see
+    /// `is_valid_calibration_factor`).
+    pub fn calibrate(&mut self, measured_millivolts: u16, known_millivolts: u16) -> bool {
+        if measured_millivolts == 0 {
+            return false;
+        }
+        let factor =
+            self.calibration_factor as u64 * known_millivolts as u64 / measured_millivolts as u64;
+        match u32::try_from(factor) {
+            Ok(factor) if is_valid_calibration_factor(factor) => {
+                self.calibration_factor = factor;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ideal_supply() {
+        let conversion = AdcConversion::default();
+        // 3.3 V supply: VREFINT reads 1.2 / 3.3 * 4095
+        let raw_vrefint = 1489;
+        assert_eq!(conversion.supply_millivolts(raw_vrefint as u16), 3300);
+        // 8.4 V battery: 2.507 V at the pin
+        let raw_battery = 3111;
+        let millivolts = conversion.battery_millivolts(raw_battery, raw_vrefint);
+        assert!(millivolts.abs_diff(8400) < 10);
+    }
+
+    #[test]
+    fn test_supply_variation_is_compensated() {
+        let conversion = AdcConversion::default();
+        // the same 7.4 V battery, read with a 3.3 V and a 3.2 V supply
+        let at_3300 = conversion.battery_millivolts(2741 * 10, 1489 * 10);
+        let at_3200 = conversion.battery_millivolts(2827 * 10, 1536 * 10);
+        assert!(at_3300.abs_diff(7400) < 10);
+        assert!(at_3200.abs_diff(7400) < 10);
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut conversion = AdcConversion::default();
+        // the resistors of this robot give a 2 % lower voltage
+        let measured = conversion.battery_millivolts(2686, 1489);
+        assert!(conversion.calibrate(measured, 7400));
+        assert!(conversion.battery_millivolts(2686, 1489).abs_diff(7400) < 5);
+        assert!(conversion.calibration_factor > UNIT_CALIBRATION_FACTOR);
+    }
+
+    #[test]
+    fn test_invalid_readings() {
+        let mut conversion = AdcConversion::default();
+        assert_eq!(conversion.battery_millivolts(1000, 0), 0);
+        assert_eq!(conversion.supply_millivolts(0), 0);
+        assert!(!conversion.calibrate(0, 7400));
+        assert_eq!(conversion.calibration_factor, UNIT_CALIBRATION_FACTOR);
+    }
+
+    #[test]
+    fn test_wrong_calibration_voltage() {
+        let mut conversion = AdcConversion::default();
+        let measured = conversion.battery_millivolts(2741, 1489);
+        // 0, a missing digit and an extra digit
+        for known_millivolts in [0, 740, 65000] {
+            assert!(!conversion.calibrate(measured, known_millivolts));
+            assert_eq!(conversion.calibration_factor, UNIT_CALIBRATION_FACTOR);
+        }
+    }
+
+    #[test]
+    fn test_calibration_factor_band() {
+        assert!(is_valid_calibration_factor(UNIT_CALIBRATION_FACTOR));
+        assert!(is_valid_calibration_factor(8_000));
+        assert!(is_valid_calibration_factor(12_000));
+        assert!(!is_valid_calibration_factor(7_999));
+        assert!(!is_valid_calibration_factor(12_001));
+        assert!(!is_valid_calibration_factor(0));
+    }
+}
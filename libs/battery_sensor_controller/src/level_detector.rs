@@ -0,0 +1,170 @@
+//! Debounced battery level detection.
+//!
+//! The voltage of the battery sags under the load of the motors and recovers when they stop, so
+//! comparing a single reading with a threshold makes the battery level flip back and forth. The
+//! `BatteryLevelDetector` uses separate thresholds for a falling and a rising state of charge
+//! (hysteresis), and only changes the level when the new one has been seen for a time window.
+
+use crate::state_of_charge::{BatteryLevel, StateOfChargeEstimator};
+
+#[derive(Clone, Copy, Debug)]
+pub struct LevelDetectorConfig {
+    /// The level falls at the warning and critical thresholds of the `StateOfChargeEstimator`, and
+    /// only rises again above them plus this margin
+    pub hysteresis_percent: u8,
+    /// Time the new level must be seen before the level changes
+    pub persistence_ms: u32,
+}
+
+impl Default for LevelDetectorConfig {
+    fn default() -> Self {
+        LevelDetectorConfig {
+            hysteresis_percent: 5,
+            persistence_ms: 2000,
+        }
+    }
+}
+
+pub struct BatteryLevelDetector {
+    pub config: LevelDetectorConfig,
+    level: BatteryLevel,
+    // The level being seen and the time it was first seen
+    candidate: Option<(BatteryLevel, u32)>,
+}
+
+impl BatteryLevelDetector {
+    pub fn new(config: LevelDetectorConfig) -> Self {
+        BatteryLevelDetector {
+            config,
+            level: BatteryLevel::Ok,
+            candidate: None,
+        }
+    }
+
+    /// The debounced battery level
+    pub fn level(&self) -> BatteryLevel {
+        self.level
+    }
+
+    /// Update the detector with a state of charge read at `now_ms`, using the thresholds of the
+    /// estimator. It returns the new level when it changes.
+    pub fn update(
+        &mut self,
+        estimator: &StateOfChargeEstimator,
+        percent: u8,
+        now_ms: u32,
+    ) -> Option<BatteryLevel> {
+        let target = self.target_level(estimator, percent);
+        if target == self.level {
+            self.candidate = None;
+            return None;
+        }
+
+        let since_ms = match self.candidate {
+            Some((level, since_ms)) if level == target => since_ms,
+            _ => {
+                self.candidate = Some((target, now_ms));
+                now_ms
+            }
+        };
+        if now_ms.wrapping_sub(since_ms) < self.config.persistence_ms {
+            return None;
+        }
+
+        self.level = target;
+        self.candidate = None;
+        Some(target)
+    }
+
+    // The level the state of charge points to, from the current level
+    fn target_level(&self, estimator: &StateOfChargeEstimator, percent: u8) -> BatteryLevel {
+        let falling = estimator.level_from_percent(percent);
+        if falling > self.level {
+            return falling;
+        }
+        let rising = estimator.level_with_margin(percent, self.config.hysteresis_percent);
+        rising.min(self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Updates a detector with the default 2S LiPo thresholds (warning 20 %, critical 5 %)
+    struct Fixture {
+        estimator: StateOfChargeEstimator,
+        detector: BatteryLevelDetector,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                estimator: StateOfChargeEstimator::new(Default::default()),
+                detector: BatteryLevelDetector::new(LevelDetectorConfig {
+                    persistence_ms: 1000,
+                    ..Default::default()
+                }),
+            }
+        }
+
+        fn update(&mut self, percent: u8, now_ms: u32) -> Option<BatteryLevel> {
+            self.detector.update(&self.estimator, percent, now_ms)
+        }
+
+        fn level(&self) -> BatteryLevel {
+            self.detector.level()
+        }
+    }
+
+    #[test]
+    fn test_level_must_persist() {
+        let mut detector = Fixture::new();
+        assert_eq!(detector.update(15, 0), None);
+        assert_eq!(detector.update(15, 999), None);
+        assert_eq!(detector.level(), BatteryLevel::Ok);
+        assert_eq!(detector.update(15, 1000), Some(BatteryLevel::Warning));
+        assert_eq!(detector.level(), BatteryLevel::Warning);
+        assert_eq!(detector.update(15, 3000), None);
+    }
+
+    #[test]
+    fn test_sags_are_ignored() {
+        let mut detector = Fixture::new();
+        // the voltage sags when the motors accelerate
+        for time_ms in (0..10_000).step_by(100) {
+            let percent = if time_ms % 1000 < 500 { 3 } else { 50 };
+            assert_eq!(detector.update(percent, time_ms), None);
+        }
+        assert_eq!(detector.level(), BatteryLevel::Ok);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut detector = Fixture::new();
+        detector.update(4, 0);
+        assert_eq!(detector.update(4, 1000), Some(BatteryLevel::Critical));
+
+        // the voltage recovers a bit when the motors stop, but not above the rising threshold
+        assert_eq!(detector.update(8, 2000), None);
+        assert_eq!(detector.update(8, 5000), None);
+        assert_eq!(detector.level(), BatteryLevel::Critical);
+
+        // a charged battery
+        assert_eq!(detector.update(90, 6000), None);
+        assert_eq!(detector.update(90, 7000), Some(BatteryLevel::Ok));
+    }
+
+    #[test]
+    fn test_falling_from_warning_to_critical() {
+        let mut detector = Fixture::new();
+        detector.update(18, 0);
+        detector.update(18, 1000);
+        assert_eq!(detector.level(), BatteryLevel::Warning);
+        // within the hysteresis band of the warning level, it stays in warning
+        assert_eq!(detector.update(22, 2000), None);
+        assert_eq!(detector.update(22, 4000), None);
+        detector.update(5, 5000);
+        assert_eq!(detector.update(5, 6000), Some(BatteryLevel::Critical));
+    }
+}
@@ -1,8 +1,27 @@
 #![no_std]
 
+pub mod conversion;
+pub mod level_detector;
+pub mod monitor;
+pub mod state_of_charge;
+
+use state_of_charge::BatteryLevel;
+
 /// The trait implemented by the battery sensor to get the battery voltage in millivolts.
 pub trait BatterySensorController {
     fn get_battery_millivolts(&mut self) -> u16;
 
-    fn is_battery_low(&mut self) -> bool;
+    /// The estimated state of charge of the battery, from 0 to 100 %
+    fn get_state_of_charge(&mut self) -> u8;
+
+    /// The battery level, debounced so the voltage sags under load don't change it
+    fn get_battery_level(&mut self) -> BatteryLevel;
+
+    /// The battery level while the motors run: the voltage is compensated with the sag under load
+    /// before estimating the state of charge, and debounced as the level at rest
+    fn get_battery_level_under_load(&mut self) -> BatteryLevel;
+
+    fn is_battery_low(&mut self) -> bool {
+        self.get_battery_level() == BatteryLevel::Critical
+    }
 }
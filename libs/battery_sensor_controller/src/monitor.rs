@@ -0,0 +1,215 @@
+//! Battery discharge monitor.
+//!
+//! The `BatteryMonitor` samples the battery voltage periodically through a
+//! `BatterySensorController`, keeps the last `N` samples in a ring buffer and estimates the
+//! discharge rate fitting a line to them (least squares), so the estimation follows the current
+//! load of the robot. With the discharge rate, it estimates the time left until the battery reaches
+//! a cutoff voltage.
+
+use crate::BatterySensorController;
+
+/// A battery voltage reading
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatterySample {
+    pub time_ms: u32,
+    pub millivolts: u16,
+}
+
+pub struct BatteryMonitor<const N: usize> {
+    /// Time between samples
+    pub period_ms: u32,
+    samples: [BatterySample; N],
+    // Where the next sample is written
+    next: usize,
+    len: usize,
+    last_sample_ms: Option<u32>,
+}
+
+impl<const N: usize> BatteryMonitor<N> {
+    pub fn new(period_ms: u32) -> Self {
+        BatteryMonitor {
+            period_ms,
+            samples: [BatterySample::default(); N],
+            next: 0,
+            len: 0,
+            last_sample_ms: None,
+        }
+    }
+
+    /// Sample the battery if the period has elapsed since the last sample. It returns true if a
+    /// sample has been taken.
+    pub fn update(&mut self, sensor: &mut impl BatterySensorController, now_ms: u32) -> bool {
+        if let Some(last_sample_ms) = self.last_sample_ms {
+            if now_ms.wrapping_sub(last_sample_ms) < self.period_ms {
+                return false;
+            }
+        }
+        let millivolts = sensor.get_battery_millivolts();
+        self.record(now_ms, millivolts);
+        true
+    }
+
+    /// Add a sample to the history, overwriting the oldest one if the history is full
+    pub fn record(&mut self, time_ms: u32, millivolts: u16) {
+        if N == 0 {
+            return;
+        }
+        self.samples[self.next] = BatterySample {
+            time_ms,
+            millivolts,
+        };
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        self.last_sample_ms = Some(time_ms);
+    }
+
+    /// Forget all the samples, e.g. after changing the battery
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+        self.last_sample_ms = None;
+    }
+
+    /// The samples in the history, from the oldest to the newest
+    pub fn samples(&self) -> impl Iterator<Item = BatterySample> + '_ {
+        let first = (self.next + N - self.len) % N.max(1);
+        (0..self.len).map(move |i| self.samples[(first + i) % N])
+    }
+
+    pub fn latest(&self) -> Option<BatterySample> {
+        self.samples().last()
+    }
+
+    /// The discharge rate in millivolts per hour (positive while discharging), or None if there
+    /// are not enough samples
+    pub fn discharge_rate(&self) -> Option<i32> {
+        let first = self.samples().next()?;
+
+        // Least squares fit of the voltage against the time (in seconds since the first sample).
+        // The sums are integers, so the fit doesn't need the floating point routines.
+        let n = self.len as i64;
+        let (mut sum_t, mut sum_v, mut sum_tt, mut sum_tv) = (0i64, 0i64, 0i64, 0i64);
+        for sample in self.samples() {
+            let t = (sample.time_ms.wrapping_sub(first.time_ms) / 1000) as i64;
+            let v = sample.millivolts as i64;
+            sum_t += t;
+            sum_v += v;
+            sum_tt += t * t;
+            sum_tv += t * v;
+        }
+        let denominator = n * sum_tt - sum_t * sum_t;
+        if self.len < 2 || denominator <= 0 {
+            return None;
+        }
+        // The slope is in millivolts per second
+        let slope_per_hour = (n * sum_tv - sum_t * sum_v) * 3600 / denominator;
+        Some(-slope_per_hour.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+
+    /// The estimated time until the battery reaches `cutoff_millivolts` at the current discharge
+    /// rate, or None if the battery is not discharging (or there are not enough samples)
+    pub fn remaining_ms(&self, cutoff_millivolts: u16) -> Option<u32> {
+        let latest = self.latest()?;
+        let rate = self.discharge_rate()?;
+        if rate <= 0 {
+            return None;
+        }
+        let margin_millivolts = latest.millivolts.saturating_sub(cutoff_millivolts) as u64;
+        let remaining_ms = margin_millivolts * 3_600_000 / rate as u64;
+        u32::try_from(remaining_ms).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_of_charge::BatteryLevel;
+    use core::cell::Cell;
+
+    // A battery that follows a synthetic voltage trace
+    struct MockBattery<F: FnMut() -> u16> {
+        trace: F,
+    }
+
+    impl<F: FnMut() -> u16> BatterySensorController for MockBattery<F> {
+        fn get_battery_millivolts(&mut self) -> u16 {
+            (self.trace)()
+        }
+
+        fn get_state_of_charge(&mut self) -> u8 {
+            0
+        }
+
+        fn get_battery_level(&mut self) -> BatteryLevel {
+            BatteryLevel::Ok
+        }
+
+        fn get_battery_level_under_load(&mut self) -> BatteryLevel {
+            BatteryLevel::Ok
+        }
+    }
+
+    #[test]
+    fn test_sampling_period() {
+        let mut battery = MockBattery { trace: || 8000 };
+        let mut monitor: BatteryMonitor<8> = BatteryMonitor::new(1000);
+        assert!(monitor.update(&mut battery, 0));
+        assert!(!monitor.update(&mut battery, 500));
+        assert!(monitor.update(&mut battery, 1000));
+        assert!(!monitor.update(&mut battery, 1999));
+        assert_eq!(monitor.samples().count(), 2);
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut monitor: BatteryMonitor<4> = BatteryMonitor::new(1000);
+        for i in 0..6 {
+            monitor.record(i * 1000, 8000 - i as u16);
+        }
+        let times: [u32; 4] = core::array::from_fn(|i| monitor.samples().nth(i).unwrap().time_ms);
+        assert_eq!(times, [2000, 3000, 4000, 5000]);
+        assert_eq!(monitor.latest().unwrap().millivolts, 7995);
+
+        monitor.clear();
+        assert_eq!(monitor.samples().count(), 0);
+        assert_eq!(monitor.discharge_rate(), None);
+    }
+
+    #[test]
+    fn test_linear_discharge() {
+        // 20 mV per minute with some noise, sampled every 10 seconds
+        let time_ms = Cell::new(0u32);
+        let mut noise = 0u32;
+        let mut battery = MockBattery {
+            trace: || {
+                noise = (noise * 7 + 3) % 11;
+                8000 - (time_ms.get() / 3000) as u16 + noise as u16
+            },
+        };
+        let mut monitor: BatteryMonitor<32> = BatteryMonitor::new(10_000);
+        while time_ms.get() <= 600_000 {
+            monitor.update(&mut battery, time_ms.get());
+            time_ms.set(time_ms.get() + 1000);
+        }
+
+        // 1200 mV per hour
+        let rate = monitor.discharge_rate().unwrap();
+        assert!(rate > 1140 && rate < 1260);
+
+        // about 7800 mV now, 600 mV to the cutoff: 30 minutes
+        let remaining_ms = monitor.remaining_ms(7200).unwrap();
+        assert!(remaining_ms > 28 * 60_000 && remaining_ms < 32 * 60_000);
+    }
+
+    #[test]
+    fn test_not_discharging() {
+        let mut monitor: BatteryMonitor<8> = BatteryMonitor::new(1000);
+        monitor.record(0, 8000);
+        assert_eq!(monitor.discharge_rate(), None);
+        // the voltage recovers after the motors stop
+        monitor.record(1000, 8010);
+        monitor.record(2000, 8020);
+        assert!(monitor.discharge_rate().unwrap() < 0);
+        assert_eq!(monitor.remaining_ms(7200), None);
+    }
+}
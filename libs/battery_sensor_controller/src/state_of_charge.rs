@@ -0,0 +1,255 @@
+//! LiPo state of charge estimation.
+//!
+//! The state of charge is estimated from the voltage of the battery with a lookup table of the
+//! discharge curve of a single cell, interpolating linearly between its points. The curve is the
+//! one of the resting voltage: the voltage drops under the load of the motors, so the battery
+//! looks emptier than it is while they run. `percent_under_load` compensates that drop with the
+//! typical sag of a cell.
+
+/// A point of a discharge curve: the voltage of a cell and its state of charge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DischargePoint {
+    pub cell_millivolts: u16,
+    pub percent: u8,
+}
+
+const fn point(cell_millivolts: u16, percent: u8) -> DischargePoint {
+    DischargePoint {
+        cell_millivolts,
+        percent,
+    }
+}
+
+/// Typical discharge curve of a LiPo cell, from full to empty. 3.2 V is taken as the empty
+/// voltage, discharging a cell below it damages it.
+pub const LIPO_DISCHARGE_CURVE: &[DischargePoint] = &[
+    point(4200, 100),
+    point(4150, 95),
+    point(4110, 90),
+    point(4080, 85),
+    point(4020, 80),
+    point(3980, 75),
+    point(3950, 70),
+    point(3910, 65),
+    point(3870, 60),
+    point(3850, 55),
+    point(3840, 50),
+    point(3820, 45),
+    point(3800, 40),
+    point(3790, 35),
+    point(3770, 30),
+    point(3750, 25),
+    point(3730, 20),
+    point(3710, 15),
+    point(3690, 10),
+    point(3610, 5),
+    point(3200, 0),
+];
+
+/// How much charge is left in the battery, from the best to the worst level
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BatteryLevel {
+    Ok,
+    /// The battery should be changed soon
+    Warning,
+    /// The battery must be changed now, the cells may be damaged if the robot keeps running
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StateOfChargeConfig {
+    /// Number of cells in series of the battery
+    pub cells: u8,
+    /// Discharge curve of a single cell, sorted from full to empty
+    pub discharge_curve: &'static [DischargePoint],
+    /// The battery level is `Warning` at or below this state of charge
+    pub warning_percent: u8,
+    /// The battery level is `Critical` at or below this state of charge
+    pub critical_percent: u8,
+    /// Typical voltage drop of a cell under the load of the motors
+    pub load_sag_cell_millivolts: u16,
+}
+
+impl Default for StateOfChargeConfig {
+    /// A 2S LiPo battery, like the one of the MightyBugA. It is critical at 5 %, about 7.2 V at
+    /// rest and about 6.4 V with the motors running, instead of the 4.9 V limit the robot had
+    /// before the state of charge was estimated (the cells are already damaged at 4.9 V).
+    fn default() -> Self {
+        StateOfChargeConfig {
+            cells: 2,
+            discharge_curve: LIPO_DISCHARGE_CURVE,
+            warning_percent: 20,
+            critical_percent: 5,
+            load_sag_cell_millivolts: 400,
+        }
+    }
+}
+
+pub struct StateOfChargeEstimator {
+    pub config: StateOfChargeConfig,
+}
+
+impl StateOfChargeEstimator {
+    pub fn new(config: StateOfChargeConfig) -> Self {
+        StateOfChargeEstimator { config }
+    }
+
+    /// Get the state of charge, from 0 to 100 %, of a battery with the given voltage
+    pub fn percent(&self, battery_millivolts: u16) -> u8 {
+        let curve = self.config.discharge_curve;
+        let cell_millivolts = battery_millivolts / self.config.cells.max(1) as u16;
+
+        let (first, last) = match (curve.first(), curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0,
+        };
+        if cell_millivolts >= first.cell_millivolts {
+            return first.percent;
+        }
+        if cell_millivolts <= last.cell_millivolts {
+            return last.percent;
+        }
+
+        for pair in curve.windows(2) {
+            let (high, low) = (pair[0], pair[1]);
+            if cell_millivolts >= low.cell_millivolts {
+                let span_mv = (high.cell_millivolts - low.cell_millivolts).max(1) as u32;
+                let span_percent = (high.percent - low.percent) as u32;
+                let offset_mv = (cell_millivolts - low.cell_millivolts) as u32;
+                return low.percent + (offset_mv * span_percent / span_mv) as u8;
+            }
+        }
+        last.percent
+    }
+
+    /// Get the state of charge of a battery with the given voltage, read while the motors run
+    pub fn percent_under_load(&self, battery_millivolts: u16) -> u8 {
+        let sag_millivolts = self.config.load_sag_cell_millivolts * self.config.cells as u16;
+        self.percent(battery_millivolts.saturating_add(sag_millivolts))
+    }
+
+    /// Get the voltage of a battery with the given state of charge (the inverse of `percent`)
+    pub fn millivolts(&self, percent: u8) -> u16 {
+        let curve = self.config.discharge_curve;
+        let cells = self.config.cells.max(1) as u16;
+
+        let cell_millivolts = match curve.windows(2).find(|pair| percent >= pair[1].percent) {
+            Some(pair) => {
+                let (high, low) = (pair[0], pair[1]);
+                let span_mv = (high.cell_millivolts - low.cell_millivolts) as u32;
+                let span_percent = (high.percent - low.percent).max(1) as u32;
+                let offset_percent = (percent.min(high.percent) - low.percent) as u32;
+                low.cell_millivolts + (offset_percent * span_mv / span_percent) as u16
+            }
+            None => curve.last().map_or(0, |last| last.cell_millivolts),
+        };
+        cell_millivolts * cells
+    }
+
+    /// True if `battery_millivolts` is between the voltages of the empty and the full battery, e.g.
+    /// to check a voltage typed by the user
+    pub fn is_within_curve(&self, battery_millivolts: u16) -> bool {
+        (self.millivolts(0)..=self.millivolts(100)).contains(&battery_millivolts)
+    }
+
+    /// Get the level of a battery with the given voltage
+    pub fn level(&self, battery_millivolts: u16) -> BatteryLevel {
+        self.level_from_percent(self.percent(battery_millivolts))
+    }
+
+    /// Get the level of a battery with the given state of charge
+    pub fn level_from_percent(&self, percent: u8) -> BatteryLevel {
+        self.level_with_margin(percent, 0)
+    }
+
+    /// Get the level of a battery with the given state of charge, raising the warning and critical
+    /// thresholds by `margin_percent`
+    pub fn level_with_margin(&self, percent: u8, margin_percent: u8) -> BatteryLevel {
+        if percent <= self.config.critical_percent.saturating_add(margin_percent) {
+            BatteryLevel::Critical
+        } else if percent <= self.config.warning_percent.saturating_add(margin_percent) {
+            BatteryLevel::Warning
+        } else {
+            BatteryLevel::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve_points() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        assert_eq!(estimator.percent(8400), 100);
+        assert_eq!(estimator.percent(7680), 50);
+        assert_eq!(estimator.percent(6400), 0);
+    }
+
+    #[test]
+    fn test_out_of_the_curve() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        // a charger may leave the cells a bit above 4.2 V
+        assert_eq!(estimator.percent(8500), 100);
+        assert_eq!(estimator.percent(5000), 0);
+        assert_eq!(estimator.percent(0), 0);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        // 3.65 V per cell, between 3.61 V (5 %) and 3.69 V (10 %)
+        assert_eq!(estimator.percent(7300), 7);
+    }
+
+    #[test]
+    fn test_cell_count() {
+        let estimator = StateOfChargeEstimator::new(StateOfChargeConfig {
+            cells: 3,
+            ..Default::default()
+        });
+        assert_eq!(estimator.percent(12600), 100);
+        assert_eq!(estimator.percent(8400), 0);
+    }
+
+    #[test]
+    fn test_millivolts() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        assert_eq!(estimator.millivolts(100), 8400);
+        assert_eq!(estimator.millivolts(5), 7220);
+        assert_eq!(estimator.millivolts(0), 6400);
+        assert_eq!(estimator.percent(estimator.millivolts(42)), 42);
+    }
+
+    #[test]
+    fn test_within_curve() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        assert!(estimator.is_within_curve(6400));
+        assert!(estimator.is_within_curve(7400));
+        assert!(estimator.is_within_curve(8400));
+        assert!(!estimator.is_within_curve(0));
+        assert!(!estimator.is_within_curve(74));
+        assert!(!estimator.is_within_curve(6399));
+        assert!(!estimator.is_within_curve(8401));
+    }
+
+    #[test]
+    fn test_levels() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        assert_eq!(estimator.level(8000), BatteryLevel::Ok);
+        assert_eq!(estimator.level(7460), BatteryLevel::Warning);
+        assert_eq!(estimator.level(7200), BatteryLevel::Critical);
+        assert_eq!(estimator.level(4900), BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn test_under_load() {
+        let estimator = StateOfChargeEstimator::new(Default::default());
+        assert_eq!(estimator.percent_under_load(7600), 100);
+        // critical under load about 6.4 V
+        assert_eq!(estimator.percent_under_load(6420), 5);
+        assert!(estimator.percent_under_load(6500) > 5);
+        assert_eq!(estimator.percent_under_load(0), 0);
+    }
+}
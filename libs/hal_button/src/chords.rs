@@ -0,0 +1,137 @@
+// Detection of button chords (several buttons pressed at the same time).
+//
+// The detector is fed with the press and release edges of up to 8 buttons, identified by their
+// index. A chord starts with the press of any button and ends when all the buttons have been
+// released: the chord is made of all the buttons pressed in between. So a single button gives a
+// chord of one button, and pressing 1 and 3 together gives the chord 1+3 (not a press of 1 and
+// another of 3), no matter which one is pressed or released first.
+
+/// A set of buttons, as a bit mask of their indexes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord(u8);
+
+impl Chord {
+    /// The chord made of the buttons with the given indexes (0 to 7)
+    pub const fn of(buttons: &[u8]) -> Self {
+        let mut mask = 0;
+        let mut i = 0;
+        while i < buttons.len() {
+            mask |= 1 << buttons[i];
+            i += 1;
+        }
+        Chord(mask)
+    }
+
+    pub const fn mask(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, button: u8) -> bool {
+        self.0 & (1 << button) != 0
+    }
+
+    /// Number of buttons of the chord
+    pub fn len(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+pub struct ChordDetector {
+    // Buttons pressed now
+    pressed: u8,
+    // Buttons pressed since the chord started
+    chord: u8,
+}
+
+impl Default for ChordDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChordDetector {
+    pub const fn new() -> Self {
+        ChordDetector {
+            pressed: 0,
+            chord: 0,
+        }
+    }
+
+    /// Forget the chord in progress. The buttons still pressed are ignored until they are released.
+    pub fn reset(&mut self) {
+        self.pressed = 0;
+        self.chord = 0;
+    }
+
+    /// Update the detector with an edge of the button `button`. It returns the chord when all its
+    /// buttons have been released.
+    pub fn update(&mut self, button: u8, pressed: bool) -> Option<Chord> {
+        let bit = 1 << button;
+        if pressed {
+            self.pressed |= bit;
+            self.chord |= bit;
+            return None;
+        }
+
+        // The release of a button pressed before the reset
+        if self.pressed & bit == 0 {
+            return None;
+        }
+        self.pressed &= !bit;
+        if self.pressed != 0 {
+            return None;
+        }
+        let chord = Chord(self.chord);
+        self.chord = 0;
+        Some(chord)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_button() {
+        let mut detector = ChordDetector::new();
+        assert_eq!(detector.update(1, true), None);
+        assert_eq!(detector.update(1, false), Some(Chord::of(&[1])));
+        assert_eq!(detector.update(2, true), None);
+        assert_eq!(detector.update(2, false), Some(Chord::of(&[2])));
+    }
+
+    #[test]
+    fn test_chord() {
+        let mut detector = ChordDetector::new();
+        // 0 and 2 pressed together, released in any order
+        assert_eq!(detector.update(0, true), None);
+        assert_eq!(detector.update(2, true), None);
+        assert_eq!(detector.update(0, false), None);
+        // pressed again while the other one is still pressed
+        assert_eq!(detector.update(0, true), None);
+        assert_eq!(detector.update(2, false), None);
+        let chord = detector.update(0, false).unwrap();
+        assert_eq!(chord, Chord::of(&[0, 2]));
+        assert_eq!(chord.len(), 2);
+        assert!(chord.contains(2));
+        assert!(!chord.contains(1));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut detector = ChordDetector::new();
+        detector.update(0, true);
+        detector.reset();
+        // the release of the button pressed before the reset is ignored
+        assert_eq!(detector.update(0, false), None);
+        detector.update(1, true);
+        detector.reset();
+        detector.update(2, true);
+        assert_eq!(detector.update(1, false), None);
+        assert_eq!(detector.update(2, false), Some(Chord::of(&[2])));
+    }
+}
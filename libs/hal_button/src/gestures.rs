@@ -0,0 +1,306 @@
+// Time based debouncing and gesture detection of a button.
+//
+// The detector is fed with the raw state of the button and the current time, so it doesn't depend
+// on any timer: the caller decides the clock source (and the tests use a fake one). It can be fed
+// polling the pin periodically, or with the timestamped edges of the pin (e.g. captured by an
+// interrupt) plus a periodic update with the current state to expire the timeouts.
+//
+// A raw state change is only accepted once it has been stable for `debounce_ms`. With the debounced
+// state, the detector produces these events:
+//  - Pressed and Released, on every debounced change.
+//  - Click, when a short press is not followed by another press within `double_click_ms` (so a
+//    double click doesn't give a click first).
+//  - DoubleClick, on the release of the second of two short presses.
+//  - LongPress(duration_ms), on the release of a press of `long_press_ms` or more.
+
+/// The events produced by a button
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    Click,
+    DoubleClick,
+    LongPress(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GestureConfig {
+    /// Time a raw state must be stable to be accepted
+    pub debounce_ms: u32,
+    /// Maximum time between the release of a click and the next press to make a double click
+    pub double_click_ms: u32,
+    /// Minimum duration of a long press
+    pub long_press_ms: u32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            debounce_ms: 20,
+            double_click_ms: 300,
+            long_press_ms: 800,
+        }
+    }
+}
+
+// Maximum number of events produced by a single update (e.g. Released, Click and LongPress, and
+// Pressed with no debounce time)
+const MAX_PENDING_EVENTS: usize = 4;
+
+pub struct ButtonGestureDetector {
+    pub config: GestureConfig,
+    // Last raw state and when it changed
+    raw_state: bool,
+    raw_since_ms: u32,
+    // Debounced state
+    pressed: bool,
+    press_start_ms: u32,
+    // A click waiting to know if it is part of a double click, and when it was released
+    pending_click: Option<u32>,
+    // The button was pressed when the detector was reset, its release is ignored
+    ignore_release: bool,
+    events: [Option<ButtonEvent>; MAX_PENDING_EVENTS],
+}
+
+impl ButtonGestureDetector {
+    pub fn new(config: GestureConfig) -> Self {
+        ButtonGestureDetector {
+            config,
+            raw_state: false,
+            raw_since_ms: 0,
+            pressed: false,
+            press_start_ms: 0,
+            pending_click: None,
+            ignore_release: false,
+            events: [None; MAX_PENDING_EVENTS],
+        }
+    }
+
+    /// The debounced state of the button
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Forget the pending events and take `pressed` as the current state. If the button is
+    /// pressed, its release doesn't produce any event, so a button still pressed from a previous
+    /// action is not taken as a new one.
+    pub fn reset(&mut self, pressed: bool, now_ms: u32) {
+        self.raw_state = pressed;
+        self.raw_since_ms = now_ms;
+        self.pressed = pressed;
+        self.press_start_ms = now_ms;
+        self.pending_click = None;
+        self.ignore_release = pressed;
+        self.events = [None; MAX_PENDING_EVENTS];
+    }
+
+    /// Update the detector with the raw state of the button read at `now_ms`. It returns the next
+    /// event, if any. Call it until it returns None to get all the events.
+    pub fn update(&mut self, raw_pressed: bool, now_ms: u32) -> Option<ButtonEvent> {
+        // The previous raw state may have been stable until now (when fed with edges)
+        self.accept_stable_state(now_ms);
+        if raw_pressed != self.raw_state {
+            self.raw_state = raw_pressed;
+            self.raw_since_ms = now_ms;
+        }
+        self.accept_stable_state(now_ms);
+
+        if let Some(released_ms) = self.pending_click {
+            if !self.pressed && now_ms.wrapping_sub(released_ms) >= self.config.double_click_ms {
+                self.pending_click = None;
+                self.push(ButtonEvent::Click);
+            }
+        }
+
+        self.pop()
+    }
+
+    fn accept_stable_state(&mut self, now_ms: u32) {
+        let stable = now_ms.wrapping_sub(self.raw_since_ms) >= self.config.debounce_ms;
+        if stable && self.raw_state != self.pressed {
+            // The state is accepted when it became stable, which is earlier than now if the update
+            // comes late (e.g. the next edge of the pin)
+            let accepted_ms = self.raw_since_ms.wrapping_add(self.config.debounce_ms);
+            self.pressed = self.raw_state;
+            match self.pressed {
+                true => self.on_press(accepted_ms),
+                false => self.on_release(accepted_ms),
+            }
+        }
+    }
+
+    fn on_press(&mut self, now_ms: u32) {
+        self.press_start_ms = now_ms;
+        self.push(ButtonEvent::Pressed);
+    }
+
+    fn on_release(&mut self, now_ms: u32) {
+        if self.ignore_release {
+            self.ignore_release = false;
+            return;
+        }
+        self.push(ButtonEvent::Released);
+
+        let duration_ms = now_ms.wrapping_sub(self.press_start_ms);
+        if duration_ms >= self.config.long_press_ms {
+            // a click before the long press is not part of a double click
+            if self.pending_click.take().is_some() {
+                self.push(ButtonEvent::Click);
+            }
+            self.push(ButtonEvent::LongPress(duration_ms));
+        } else if self.pending_click.take().is_some() {
+            self.push(ButtonEvent::DoubleClick);
+        } else {
+            self.pending_click = Some(now_ms);
+        }
+    }
+
+    fn push(&mut self, event: ButtonEvent) {
+        if let Some(slot) = self.events.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(event);
+        }
+    }
+
+    fn pop(&mut self) -> Option<ButtonEvent> {
+        let event = self.events[0].take();
+        self.events.rotate_left(1);
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feed the detector with a raw state from `from_ms` to `to_ms` (excluded), one update per
+    // millisecond, and collect the events
+    fn run(
+        detector: &mut ButtonGestureDetector,
+        raw_pressed: bool,
+        from_ms: u32,
+        to_ms: u32,
+        events: &mut [Option<ButtonEvent>; 8],
+    ) {
+        for now_ms in from_ms..to_ms {
+            while let Some(event) = detector.update(raw_pressed, now_ms) {
+                if let Some(slot) = events.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(event);
+                }
+            }
+        }
+    }
+
+    fn detector() -> ButtonGestureDetector {
+        ButtonGestureDetector::new(GestureConfig::default())
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        // bounces shorter than the debounce time
+        for i in 0..10 {
+            run(&mut detector, i % 2 == 0, i * 5, i * 5 + 5, &mut events);
+        }
+        assert_eq!(events, [None; 8]);
+        assert!(!detector.is_pressed());
+
+        run(&mut detector, true, 50, 100, &mut events);
+        assert_eq!(events[0], Some(ButtonEvent::Pressed));
+        assert!(detector.is_pressed());
+    }
+
+    #[test]
+    fn test_click() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        run(&mut detector, true, 0, 100, &mut events);
+        run(&mut detector, false, 100, 300, &mut events);
+        // waiting for a possible double click
+        assert_eq!(events[2], None);
+        run(&mut detector, false, 300, 1000, &mut events);
+        assert_eq!(
+            events[..3],
+            [
+                Some(ButtonEvent::Pressed),
+                Some(ButtonEvent::Released),
+                Some(ButtonEvent::Click)
+            ]
+        );
+        assert_eq!(events[3], None);
+    }
+
+    #[test]
+    fn test_double_click() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        run(&mut detector, true, 0, 100, &mut events);
+        run(&mut detector, false, 100, 200, &mut events);
+        run(&mut detector, true, 200, 300, &mut events);
+        run(&mut detector, false, 300, 1000, &mut events);
+        assert_eq!(
+            events[..5],
+            [
+                Some(ButtonEvent::Pressed),
+                Some(ButtonEvent::Released),
+                Some(ButtonEvent::Pressed),
+                Some(ButtonEvent::Released),
+                Some(ButtonEvent::DoubleClick)
+            ]
+        );
+        assert_eq!(events[5], None);
+    }
+
+    #[test]
+    fn test_long_press() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        run(&mut detector, true, 0, 1500, &mut events);
+        run(&mut detector, false, 1500, 2000, &mut events);
+        assert_eq!(events[1], Some(ButtonEvent::Released));
+        assert_eq!(events[2], Some(ButtonEvent::LongPress(1500)));
+        assert_eq!(events[3], None);
+    }
+
+    #[test]
+    fn test_edges() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        // the edges of a short press with some bounces, and a late update
+        for (raw_pressed, time_ms) in [(true, 1000), (false, 1002), (true, 1003), (false, 1090)] {
+            while let Some(event) = detector.update(raw_pressed, time_ms) {
+                if let Some(slot) = events.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(event);
+                }
+            }
+        }
+        run(&mut detector, false, 1500, 1501, &mut events);
+        assert_eq!(
+            events[..4],
+            [
+                Some(ButtonEvent::Pressed),
+                Some(ButtonEvent::Released),
+                Some(ButtonEvent::Click),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reset_while_pressed() {
+        let mut detector = detector();
+        let mut events = [None; 8];
+        run(&mut detector, true, 0, 100, &mut events);
+        // the button is still pressed from the previous action
+        detector.reset(true, 100);
+        events = [None; 8];
+        run(&mut detector, true, 100, 200, &mut events);
+        run(&mut detector, false, 200, 1000, &mut events);
+        assert_eq!(events, [None; 8]);
+
+        // a new click
+        run(&mut detector, true, 1000, 1100, &mut events);
+        run(&mut detector, false, 1100, 2000, &mut events);
+        assert_eq!(events[2], Some(ButtonEvent::Click));
+    }
+}
@@ -2,12 +2,26 @@
 
 use embedded_hal::digital::v2::InputPin;
 
+pub mod chords;
+pub mod gestures;
+pub use chords::{Chord, ChordDetector};
+pub use gestures::{ButtonEvent, ButtonGestureDetector, GestureConfig};
+
 pub trait ButtonController {
-    // This function returns true if the button is on pressed state
+    // This function returns true if the button is on pressed state (raw, not debounced)
     fn is_pressed(&self) -> bool;
 
     // This function returns true if the button changed its state since the last time this function was called
     fn is_changed(&mut self) -> bool;
+
+    // This function debounces the button and returns the next event (press, release, click, double
+    // click or long press). `now_ms` is the current time, from any millisecond clock. It must be
+    // called periodically (every few milliseconds) and until it returns None to get all the events.
+    fn poll_event(&mut self, now_ms: u32) -> Option<ButtonEvent>;
+
+    // This function discards the pending events. If the button is pressed, its release is ignored,
+    // so a button still pressed from a previous action is not taken as a new press.
+    fn reset_events(&mut self, now_ms: u32);
 }
 
 // This struct handles the resources and state regarding a button.
@@ -16,14 +30,21 @@ pub trait ButtonController {
 pub struct Button<Pin: InputPin, const PULLED_UP: bool> {
     pin: Pin,
     last_state: bool,
+    gestures: ButtonGestureDetector,
 }
 
 // Implementation of the Button struct based on the ButtonController and InputPin traits
 impl<Pin: InputPin, const PULLED_UP: bool> Button<Pin, PULLED_UP> {
     pub fn new(pin: Pin) -> Self {
+        Self::with_config(pin, GestureConfig::default())
+    }
+
+    // Create a button with custom debounce and gestures timings
+    pub fn with_config(pin: Pin, config: GestureConfig) -> Self {
         Button {
             pin,
             last_state: false,
+            gestures: ButtonGestureDetector::new(config),
         }
     }
 }
@@ -45,4 +66,67 @@ impl<const PULLED_UP: bool, Pin: InputPin> ButtonController for Button<Pin, PULL
         self.last_state = current_state;
         changed
     }
+
+    fn poll_event(&mut self, now_ms: u32) -> Option<ButtonEvent> {
+        let pressed = self.is_pressed();
+        self.gestures.update(pressed, now_ms)
+    }
+
+    fn reset_events(&mut self, now_ms: u32) {
+        let pressed = self.is_pressed();
+        self.gestures.reset(pressed, now_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    // A pin whose level is set by the test
+    struct MockPin<'a> {
+        high: &'a Cell<bool>,
+    }
+
+    impl InputPin for MockPin<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.high.get())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.high.get())
+        }
+    }
+
+    #[test]
+    fn test_pulled_up_button_gestures() {
+        let high = Cell::new(true);
+        let mut button: Button<MockPin, true> = Button::new(MockPin { high: &high });
+        assert!(!button.is_pressed());
+
+        high.set(false);
+        assert!(button.is_pressed());
+        assert_eq!(button.poll_event(0), None);
+        assert_eq!(button.poll_event(20), Some(ButtonEvent::Pressed));
+
+        high.set(true);
+        assert_eq!(button.poll_event(100), None);
+        assert_eq!(button.poll_event(120), Some(ButtonEvent::Released));
+        assert_eq!(button.poll_event(500), Some(ButtonEvent::Click));
+        assert_eq!(button.poll_event(510), None);
+    }
+
+    #[test]
+    fn test_reset_events() {
+        let high = Cell::new(true);
+        let mut button: Button<MockPin, false> = Button::new(MockPin { high: &high });
+        button.reset_events(0);
+        high.set(false);
+        for now_ms in (0..1000).step_by(10) {
+            assert_eq!(button.poll_event(now_ms), None);
+        }
+    }
 }
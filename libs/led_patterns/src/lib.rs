@@ -0,0 +1,275 @@
+// LED patterns
+//
+// Non-blocking blinking patterns for LEDs. A pattern tells if the LED is on at any time since it
+// started, and a runner keeps the pattern of a LED and drives its pin from a periodic tick (the
+// control loop of the application), so the LEDs blink while the application keeps working. The pin
+// is only written when the LED changes.
+//
+// The pins are not owned by the runners, they are passed to every tick, so the runners can be kept
+// apart from the board that owns the pins.
+#![no_std]
+
+use embedded_hal::digital::v2::OutputPin;
+
+// Timings of the heartbeat: two short flashes every period
+const HEARTBEAT_PERIOD_MS: u32 = 1200;
+const HEARTBEAT_FLASH_MS: u32 = 100;
+const HEARTBEAT_SECOND_FLASH_MS: u32 = 250;
+
+// Timings of the pulses: `count` pulses followed by a long pause, so the pulses can be counted
+const PULSE_ON_MS: u32 = 200;
+const PULSE_PERIOD_MS: u32 = 500;
+const PULSES_PAUSE_MS: u32 = 1500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    Solid,
+    /// On the first half of every period
+    Blink {
+        period_ms: u32,
+    },
+    /// Two short flashes every 1.2 s
+    Heartbeat,
+    /// `count` short pulses and a long pause (for error codes)
+    Pulses {
+        count: u8,
+    },
+}
+
+impl Pattern {
+    /// Duration of a repetition of the pattern (0 for the patterns that don't change)
+    pub fn period_ms(&self) -> u32 {
+        match *self {
+            Pattern::Off | Pattern::Solid => 0,
+            Pattern::Blink { period_ms } => period_ms,
+            Pattern::Heartbeat => HEARTBEAT_PERIOD_MS,
+            Pattern::Pulses { count } => count as u32 * PULSE_PERIOD_MS + PULSES_PAUSE_MS,
+        }
+    }
+
+    /// Whether the LED is on `elapsed_ms` after the pattern started
+    pub fn is_on(&self, elapsed_ms: u32) -> bool {
+        // Time since the start of the current repetition
+        let time_ms = elapsed_ms.checked_rem(self.period_ms()).unwrap_or(0);
+        match *self {
+            Pattern::Off => false,
+            Pattern::Solid => true,
+            Pattern::Blink { period_ms } => time_ms < period_ms / 2,
+            Pattern::Heartbeat => {
+                time_ms < HEARTBEAT_FLASH_MS
+                    || (HEARTBEAT_SECOND_FLASH_MS..HEARTBEAT_SECOND_FLASH_MS + HEARTBEAT_FLASH_MS)
+                        .contains(&time_ms)
+            }
+            Pattern::Pulses { count } => {
+                time_ms < count as u32 * PULSE_PERIOD_MS && time_ms % PULSE_PERIOD_MS < PULSE_ON_MS
+            }
+        }
+    }
+}
+
+/// Runs the pattern of a LED
+pub struct PatternRunner {
+    pattern: Pattern,
+    start_ms: u32,
+    // Last state written to the pin (None if it must be written on the next tick)
+    on: Option<bool>,
+}
+
+impl Default for PatternRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatternRunner {
+    pub const fn new() -> Self {
+        PatternRunner {
+            pattern: Pattern::Off,
+            start_ms: 0,
+            on: None,
+        }
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Start `pattern` at `start_ms`. The pin is written on the next tick, even if it was changed
+    /// by someone else.
+    pub fn set(&mut self, pattern: Pattern, start_ms: u32) {
+        self.pattern = pattern;
+        self.start_ms = start_ms;
+        self.on = None;
+    }
+
+    /// Update the pin to `now_ms`. The errors of the pin are ignored.
+    pub fn tick(&mut self, pin: &mut impl OutputPin, now_ms: u32) {
+        let on = self.pattern.is_on(now_ms.wrapping_sub(self.start_ms));
+        if self.on == Some(on) {
+            return;
+        }
+        self.on = Some(on);
+        let _ = if on { pin.set_high() } else { pin.set_low() };
+    }
+}
+
+/// The patterns of a pair of LEDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PairPattern {
+    pub first: Pattern,
+    pub second: Pattern,
+    /// Time the second pattern goes ahead of the first one
+    pub second_phase_ms: u32,
+}
+
+impl PairPattern {
+    pub const fn new(first: Pattern, second: Pattern) -> Self {
+        PairPattern {
+            first,
+            second,
+            second_phase_ms: 0,
+        }
+    }
+
+    /// The same pattern in both LEDs, at the same time
+    pub const fn both(pattern: Pattern) -> Self {
+        Self::new(pattern, pattern)
+    }
+
+    /// Both LEDs blinking, one on while the other is off
+    pub const fn alternating(period_ms: u32) -> Self {
+        PairPattern {
+            first: Pattern::Blink { period_ms },
+            second: Pattern::Blink { period_ms },
+            second_phase_ms: period_ms / 2,
+        }
+    }
+}
+
+/// Runs the patterns of a pair of LEDs
+#[derive(Default)]
+pub struct PairRunner {
+    pub first: PatternRunner,
+    pub second: PatternRunner,
+}
+
+impl PairRunner {
+    pub const fn new() -> Self {
+        PairRunner {
+            first: PatternRunner::new(),
+            second: PatternRunner::new(),
+        }
+    }
+
+    pub fn set(&mut self, pattern: PairPattern, start_ms: u32) {
+        self.first.set(pattern.first, start_ms);
+        self.second.set(
+            pattern.second,
+            start_ms.wrapping_sub(pattern.second_phase_ms),
+        );
+    }
+
+    /// Update the pins to `now_ms`
+    pub fn tick(&mut self, first: &mut impl OutputPin, second: &mut impl OutputPin, now_ms: u32) {
+        self.first.tick(first, now_ms);
+        self.second.tick(second, now_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    // A pin that counts the writes
+    #[derive(Default)]
+    struct MockPin {
+        high: bool,
+        writes: u32,
+    }
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.high = false;
+            self.writes += 1;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.high = true;
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert!(!Pattern::Off.is_on(123));
+        assert!(Pattern::Solid.is_on(123));
+
+        let blink = Pattern::Blink { period_ms: 200 };
+        assert!(blink.is_on(0));
+        assert!(blink.is_on(99));
+        assert!(!blink.is_on(100));
+        assert!(blink.is_on(200));
+
+        let heartbeat = Pattern::Heartbeat;
+        assert!(heartbeat.is_on(50));
+        assert!(!heartbeat.is_on(150));
+        assert!(heartbeat.is_on(300));
+        assert!(!heartbeat.is_on(600));
+        assert!(heartbeat.is_on(1200));
+    }
+
+    #[test]
+    fn test_pulses() {
+        let pulses = Pattern::Pulses { count: 3 };
+        assert_eq!(pulses.period_ms(), 3000);
+        // count the pulses of a repetition
+        let mut count = 0;
+        let mut on = false;
+        for time_ms in 0..pulses.period_ms() {
+            if pulses.is_on(time_ms) && !on {
+                count += 1;
+            }
+            on = pulses.is_on(time_ms);
+        }
+        assert_eq!(count, 3);
+        assert!(!pulses.is_on(2999));
+        assert!(pulses.is_on(3000));
+    }
+
+    #[test]
+    fn test_runner() {
+        let mut pin = MockPin::default();
+        let mut runner = PatternRunner::new();
+        runner.set(Pattern::Blink { period_ms: 100 }, 1000);
+        for now_ms in 1000..1200 {
+            runner.tick(&mut pin, now_ms);
+        }
+        // on, off, on, off
+        assert_eq!(pin.writes, 4);
+        assert!(!pin.high);
+
+        // setting a pattern writes the pin again
+        runner.set(Pattern::Off, 1200);
+        runner.tick(&mut pin, 1200);
+        assert_eq!(pin.writes, 5);
+        runner.tick(&mut pin, 1300);
+        assert_eq!(pin.writes, 5);
+    }
+
+    #[test]
+    fn test_alternating() {
+        let (mut first, mut second) = (MockPin::default(), MockPin::default());
+        let mut runner = PairRunner::new();
+        runner.set(PairPattern::alternating(200), u32::MAX - 50);
+        for now_ms in (u32::MAX - 50..=u32::MAX).chain(0..500) {
+            runner.tick(&mut first, &mut second, now_ms);
+            assert_ne!(first.high, second.high);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Online recalibration of the light sensor array.
+//!
+//! The references of a static `Calibration` drift while the robot runs, as it crosses differently
+//! lit parts of the track or as the emitter LED warms up. `AdaptiveCalibration` starts from the
+//! stored calibration and slowly moves the references of each channel towards the raw values read
+//! in frames where the line has been confidently found: the channels that read close to the
+//! brightest value update the `min` reference and the ones that read close to the darkest value
+//! update the `max` one. The references never move further than a configured limit from the stored
+//! calibration, and the stored calibration can always be restored with `reset`.
+
+use crate::calibration::{Calibration, CALIBRATED_MAX, MIN_CONTRAST};
+use crate::line_position::LineReading;
+
+// The references are kept in fixed point (8 fractional bits), so small rates still move them
+const FRACTIONAL_BITS: u32 = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveCalibrationConfig {
+    /// Percentage of the difference between a reference and the new reading applied per frame
+    pub rate_percent: u8,
+    /// Minimum quality of the line reading to use a frame
+    pub min_quality: f32,
+    /// Channels whose normalized value is closer than this to 0 or to `CALIBRATED_MAX` update the
+    /// corresponding reference
+    pub reference_margin: u16,
+    /// Maximum distance (raw value) of a reference to the one of the stored calibration
+    pub max_drift: u16,
+}
+
+impl Default for AdaptiveCalibrationConfig {
+    fn default() -> Self {
+        AdaptiveCalibrationConfig {
+            rate_percent: 2,
+            min_quality: 0.7,
+            reference_margin: 200,
+            max_drift: 400,
+        }
+    }
+}
+
+pub struct AdaptiveCalibration {
+    config: AdaptiveCalibrationConfig,
+    stored: Calibration,
+    current: Calibration,
+    // Fixed point references
+    min: [u32; 8],
+    max: [u32; 8],
+}
+
+impl AdaptiveCalibration {
+    pub fn new(stored: Calibration, config: AdaptiveCalibrationConfig) -> Self {
+        AdaptiveCalibration {
+            config,
+            stored,
+            current: stored,
+            min: stored.min.map(|value| (value as u32) << FRACTIONAL_BITS),
+            max: stored.max.map(|value| (value as u32) << FRACTIONAL_BITS),
+        }
+    }
+
+    /// The calibration in use, with the adapted references
+    pub fn calibration(&self) -> &Calibration {
+        &self.current
+    }
+
+    /// The calibration the adaptation started from
+    pub fn stored_calibration(&self) -> &Calibration {
+        &self.stored
+    }
+
+    /// Normalize a raw light map with the adapted references
+    pub fn normalize(&self, light_map: &[u16; 8]) -> [u16; 8] {
+        self.current.normalize(light_map)
+    }
+
+    /// Fall back to the stored calibration
+    pub fn reset(&mut self) {
+        *self = Self::new(self.stored, self.config);
+    }
+
+    /// Adapt the references with a raw light map and the line reading estimated from it (normalized
+    /// with the current calibration). Frames without a confident reading are ignored.
+    pub fn update(&mut self, light_map: &[u16; 8], reading: Option<&LineReading>) {
+        match reading {
+            Some(reading) if reading.quality >= self.config.min_quality => {}
+            _ => return,
+        }
+
+        let normalized = self.current.normalize(light_map);
+        for (i, &value) in normalized.iter().enumerate() {
+            let raw = light_map[i];
+            let stored_min = self.stored.min[i];
+            let stored_max = self.stored.max[i];
+            if value <= self.config.reference_margin {
+                let min = self.follow(self.min[i], raw);
+                self.min[i] = clamp_drift(min, stored_min, self.config.max_drift);
+            } else if value >= CALIBRATED_MAX.saturating_sub(self.config.reference_margin) {
+                let max = self.follow(self.max[i], raw);
+                self.max[i] = clamp_drift(max, stored_max, self.config.max_drift);
+            }
+        }
+
+        // A channel without enough contrast keeps its current references
+        for i in 0..8 {
+            let min = (self.min[i] >> FRACTIONAL_BITS) as u16;
+            let max = (self.max[i] >> FRACTIONAL_BITS) as u16;
+            if max >= min && max - min >= MIN_CONTRAST {
+                self.current.min[i] = min;
+                self.current.max[i] = max;
+            } else {
+                self.min[i] = (self.current.min[i] as u32) << FRACTIONAL_BITS;
+                self.max[i] = (self.current.max[i] as u32) << FRACTIONAL_BITS;
+            }
+        }
+    }
+
+    // Move a fixed point reference towards a raw value
+    fn follow(&self, reference: u32, raw: u16) -> u32 {
+        let target = (raw as u32) << FRACTIONAL_BITS;
+        let rate = self.config.rate_percent.min(100) as u32;
+        if target >= reference {
+            reference + (target - reference) * rate / 100
+        } else {
+            reference - (reference - target) * rate / 100
+        }
+    }
+}
+
+// Keep a fixed point reference within `max_drift` of the stored one
+fn clamp_drift(reference: u32, stored: u16, max_drift: u16) -> u32 {
+    let low = (stored.saturating_sub(max_drift) as u32) << FRACTIONAL_BITS;
+    let high = (stored.saturating_add(max_drift) as u32) << FRACTIONAL_BITS;
+    reference.clamp(low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_position::{LinePolarity, LinePositionEstimator};
+
+    fn stored_calibration() -> Calibration {
+        Calibration {
+            min: [400; 8],
+            max: [3400; 8],
+            polarity: LinePolarity::DarkLine,
+        }
+    }
+
+    // Run some frames with a centered line, the background reads `background` and the line `line`
+    fn run(adaptive: &mut AdaptiveCalibration, frames: usize, background: u16, line: u16) {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        for _ in 0..frames {
+            let mut light_map = [background; 8];
+            light_map[3] = line;
+            light_map[4] = line;
+            let reading = estimator.estimate(&adaptive.normalize(&light_map));
+            adaptive.update(&light_map, reading.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_references_follow_the_drift() {
+        let mut adaptive = AdaptiveCalibration::new(stored_calibration(), Default::default());
+        // the surface is lit more, everything reads brighter
+        run(&mut adaptive, 300, 300, 3200);
+
+        let calibration = adaptive.calibration();
+        assert!(calibration.min[0] < 310 && calibration.min[0] >= 300);
+        assert!(calibration.max[3] < 3210 && calibration.max[3] >= 3200);
+        // the channels never seen over the line keep their dark reference
+        assert_eq!(calibration.max[0], 3400);
+        assert_eq!(adaptive.stored_calibration(), &stored_calibration());
+    }
+
+    #[test]
+    fn test_drift_is_limited() {
+        let config = AdaptiveCalibrationConfig {
+            max_drift: 100,
+            ..Default::default()
+        };
+        let mut adaptive = AdaptiveCalibration::new(stored_calibration(), config);
+        run(&mut adaptive, 500, 0, 3000);
+
+        assert_eq!(adaptive.calibration().min[0], 300);
+        assert_eq!(adaptive.calibration().max[4], 3300);
+    }
+
+    #[test]
+    fn test_frames_without_confident_line_are_ignored() {
+        let mut adaptive = AdaptiveCalibration::new(stored_calibration(), Default::default());
+        // no line
+        adaptive.update(&[300; 8], None);
+        // a wide patch
+        let light_map = [300, 3400, 3400, 3400, 3400, 3400, 3400, 300];
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator.estimate(&adaptive.normalize(&light_map));
+        adaptive.update(&light_map, reading.as_ref());
+
+        assert_eq!(adaptive.calibration(), &stored_calibration());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut adaptive = AdaptiveCalibration::new(stored_calibration(), Default::default());
+        run(&mut adaptive, 100, 300, 3200);
+        assert_ne!(adaptive.calibration(), &stored_calibration());
+
+        adaptive.reset();
+        assert_eq!(adaptive.calibration(), &stored_calibration());
+        assert_eq!(adaptive.normalize(&[400; 8]), [0; 8]);
+    }
+}
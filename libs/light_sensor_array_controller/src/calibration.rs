@@ -0,0 +1,192 @@
+//! Light sensor array calibration.
+//!
+//! Every sensor of the array has its own sensitivity, so the raw values are normalized with the
+//! minimum (brightest) and maximum (darkest) values seen by each channel while the robot is swept
+//! over the line. The `Calibrator` collects these values and a histogram of the readings, that is
+//! used to detect the polarity of the line: most of the readings are of the background, so if they
+//! are in the bright half of the range, the line is dark and the other way round.
+
+use crate::line_position::LinePolarity;
+
+/// The value of the darkest reading of the normalized light maps
+pub const CALIBRATED_MAX: u16 = 1000;
+
+/// The calibration fails if a channel has seen a smaller range of raw values
+pub const MIN_CONTRAST: u16 = 200;
+
+// The histogram has 16 bins of 256 raw values (12 bits ADC)
+const HISTOGRAM_BINS: usize = 16;
+const HISTOGRAM_BIN_SHIFT: u32 = 8;
+
+/// The result of a calibration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Raw value of the brightest reading of each channel
+    pub min: [u16; 8],
+    /// Raw value of the darkest reading of each channel
+    pub max: [u16; 8],
+    /// Polarity of the line detected during the calibration
+    pub polarity: LinePolarity,
+}
+
+impl Default for Calibration {
+    /// A calibration that does not change the sensors readings, only scales them from 12 bits
+    fn default() -> Self {
+        Calibration {
+            min: [0; 8],
+            max: [4095; 8],
+            polarity: LinePolarity::DarkLine,
+        }
+    }
+}
+
+impl Calibration {
+    /// Normalize a raw light map, so each channel goes from 0 (its brightest value) to
+    /// `CALIBRATED_MAX` (its darkest value)
+    pub fn normalize(&self, light_map: &[u16; 8]) -> [u16; 8] {
+        let mut normalized = [0u16; 8];
+        for (i, value) in normalized.iter_mut().enumerate() {
+            let range = self.max[i].saturating_sub(self.min[i]).max(1);
+            let offset = light_map[i].saturating_sub(self.min[i]).min(range);
+            *value = (offset as u32 * CALIBRATED_MAX as u32 / range as u32) as u16;
+        }
+        normalized
+    }
+}
+
+/// Collects the light maps read while the robot is swept over the line.
+pub struct Calibrator {
+    min: [u16; 8],
+    max: [u16; 8],
+    histogram: [[u16; HISTOGRAM_BINS]; 8],
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calibrator {
+    pub fn new() -> Self {
+        Calibrator {
+            min: [u16::MAX; 8],
+            max: [0; 8],
+            histogram: [[0; HISTOGRAM_BINS]; 8],
+        }
+    }
+
+    /// Add a raw light map to the calibration
+    pub fn update(&mut self, light_map: &[u16; 8]) {
+        for (i, &value) in light_map.iter().enumerate() {
+            self.min[i] = self.min[i].min(value);
+            self.max[i] = self.max[i].max(value);
+            let bin = ((value >> HISTOGRAM_BIN_SHIFT) as usize).min(HISTOGRAM_BINS - 1);
+            self.histogram[i][bin] = self.histogram[i][bin].saturating_add(1);
+        }
+    }
+
+    /// Get the calibration, or None if some channel has not seen enough contrast (the robot has
+    /// not been swept over the line)
+    pub fn finish(&self) -> Option<Calibration> {
+        let enough_contrast =
+            (0..8).all(|i| self.max[i] >= self.min[i] && self.max[i] - self.min[i] >= MIN_CONTRAST);
+        if !enough_contrast {
+            return None;
+        }
+        Some(Calibration {
+            min: self.min,
+            max: self.max,
+            polarity: self.detect_polarity(),
+        })
+    }
+
+    // Count the readings below and above the middle of the calibrated range of each channel
+    fn detect_polarity(&self) -> LinePolarity {
+        let (mut bright, mut dark) = (0u32, 0u32);
+        for i in 0..8 {
+            let middle = (self.min[i] as u32 + self.max[i] as u32) / 2;
+            for (bin, &count) in self.histogram[i].iter().enumerate() {
+                let bin_center =
+                    ((bin as u32) << HISTOGRAM_BIN_SHIFT) + (1 << (HISTOGRAM_BIN_SHIFT - 1));
+                if bin_center < middle {
+                    bright += count as u32;
+                } else {
+                    dark += count as u32;
+                }
+            }
+        }
+
+        match bright >= dark {
+            true => LinePolarity::DarkLine,
+            false => LinePolarity::LightLine,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sweep the array over a line: the line is under one channel at a time
+    fn sweep(calibrator: &mut Calibrator, background: u16, line: u16) {
+        for position in 0..8 {
+            let mut light_map = [background; 8];
+            light_map[position] = line;
+            calibrator.update(&light_map);
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut calibrator = Calibrator::new();
+        sweep(&mut calibrator, 400, 3400);
+        let calibration = calibrator.finish().unwrap();
+
+        assert_eq!(calibration.normalize(&[400; 8]), [0; 8]);
+        assert_eq!(calibration.normalize(&[3400; 8]), [CALIBRATED_MAX; 8]);
+        assert_eq!(calibration.normalize(&[1900; 8]), [500; 8]);
+        // out of the calibrated range
+        assert_eq!(calibration.normalize(&[100; 8]), [0; 8]);
+        assert_eq!(calibration.normalize(&[4000; 8]), [CALIBRATED_MAX; 8]);
+    }
+
+    #[test]
+    fn test_default_calibration() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.normalize(&[4095; 8]), [CALIBRATED_MAX; 8]);
+        assert_eq!(calibration.normalize(&[0; 8]), [0; 8]);
+    }
+
+    #[test]
+    fn test_not_enough_contrast() {
+        let mut calibrator = Calibrator::new();
+        assert_eq!(calibrator.finish(), None);
+
+        // the robot has not been moved
+        for _ in 0..10 {
+            calibrator.update(&[400, 400, 400, 3400, 3400, 400, 400, 400]);
+        }
+        assert_eq!(calibrator.finish(), None);
+    }
+
+    #[test]
+    fn test_dark_line_polarity() {
+        let mut calibrator = Calibrator::new();
+        sweep(&mut calibrator, 400, 3400);
+        assert_eq!(
+            calibrator.finish().unwrap().polarity,
+            LinePolarity::DarkLine
+        );
+    }
+
+    #[test]
+    fn test_light_line_polarity() {
+        let mut calibrator = Calibrator::new();
+        sweep(&mut calibrator, 3400, 400);
+        assert_eq!(
+            calibrator.finish().unwrap().polarity,
+            LinePolarity::LightLine
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Light sensor array health diagnostics.
+//!
+//! The diagnostics compare a light map read with the emitter LED off and another one read with the
+//! LED on, with the robot over a uniform surface (e.g. the white part of the track), and report the
+//! status of each channel.
+
+/// The health of a sensor channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelStatus {
+    Ok,
+    /// The channel reads 0 with the LED on and off (short circuit to ground)
+    StuckLow,
+    /// The channel reads the full scale with the LED on and off (open circuit)
+    StuckHigh,
+    /// The channel does not change when the emitter LED is turned on
+    NoEmitterResponse,
+    /// The emitter response of the channel is very different from its neighbours ones
+    InconsistentWithNeighbours,
+}
+
+impl ChannelStatus {
+    /// A dead channel can't be used to follow the line
+    pub fn is_dead(&self) -> bool {
+        matches!(
+            self,
+            ChannelStatus::StuckLow | ChannelStatus::StuckHigh | ChannelStatus::NoEmitterResponse
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DiagnosticsConfig {
+    /// The maximum value given by the ADC
+    pub full_scale: u16,
+    /// Values closer than this to 0 or to the full scale are stuck
+    pub stuck_margin: u16,
+    /// Minimum change of a channel when the emitter is turned on
+    pub min_emitter_response: u16,
+    /// Maximum difference between the emitter response of a channel and the mean response of its
+    /// neighbours
+    pub max_neighbour_difference: u16,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            full_scale: 4095,
+            stuck_margin: 10,
+            min_emitter_response: 300,
+            max_neighbour_difference: 1500,
+        }
+    }
+}
+
+/// Check every channel of the sensor array. The sensors output lower values when they receive more
+/// light, so a healthy channel reads a lower value with the emitter LED on.
+pub fn diagnose(
+    led_off: &[u16; 8],
+    led_on: &[u16; 8],
+    config: &DiagnosticsConfig,
+) -> [ChannelStatus; 8] {
+    let response: [u16; 8] = core::array::from_fn(|i| led_off[i].saturating_sub(led_on[i]));
+
+    let high = config.full_scale.saturating_sub(config.stuck_margin);
+    let mut report: [ChannelStatus; 8] = core::array::from_fn(|i| {
+        if led_off[i] <= config.stuck_margin && led_on[i] <= config.stuck_margin {
+            ChannelStatus::StuckLow
+        } else if led_off[i] >= high && led_on[i] >= high {
+            ChannelStatus::StuckHigh
+        } else if response[i] < config.min_emitter_response {
+            ChannelStatus::NoEmitterResponse
+        } else {
+            ChannelStatus::Ok
+        }
+    });
+
+    // The dead neighbours are left out of the mean, or the healthy channels next to them would
+    // look inconsistent
+    for i in 0..8 {
+        if report[i].is_dead() {
+            continue;
+        }
+        let (mut sum, mut count) = (0u32, 0u32);
+        for neighbour in [i.wrapping_sub(1), i + 1] {
+            if neighbour < 8 && !report[neighbour].is_dead() {
+                sum += response[neighbour] as u32;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            continue;
+        }
+        let neighbours_mean = (sum / count) as u16;
+        if response[i].abs_diff(neighbours_mean) > config.max_neighbour_difference {
+            report[i] = ChannelStatus::InconsistentWithNeighbours;
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_healthy_array() {
+        let led_off = [3900, 3850, 3950, 3900, 3920, 3880, 3900, 3910];
+        let led_on = [400, 500, 350, 420, 380, 450, 400, 600];
+        let report = diagnose(&led_off, &led_on, &Default::default());
+        assert_eq!(report, [ChannelStatus::Ok; 8]);
+    }
+
+    #[test]
+    fn test_dead_channels() {
+        let mut led_off = [3900; 8];
+        let mut led_on = [400; 8];
+        // short circuit
+        led_off[0] = 0;
+        led_on[0] = 3;
+        // open circuit
+        led_off[3] = 4095;
+        led_on[3] = 4095;
+        // broken phototransistor or emitter
+        led_off[7] = 3900;
+        led_on[7] = 3800;
+
+        let report = diagnose(&led_off, &led_on, &Default::default());
+        assert_eq!(report[0], ChannelStatus::StuckLow);
+        assert_eq!(report[3], ChannelStatus::StuckHigh);
+        assert_eq!(report[7], ChannelStatus::NoEmitterResponse);
+        assert!(report[0].is_dead() && report[3].is_dead() && report[7].is_dead());
+        // the healthy channels next to the dead ones are not taken as inconsistent
+        for i in [1, 2, 4, 5, 6] {
+            assert_eq!(report[i], ChannelStatus::Ok);
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_channel() {
+        let led_off = [3900; 8];
+        let mut led_on = [400; 8];
+        // this channel is much less sensitive than the others
+        led_on[4] = 3000;
+
+        let report = diagnose(&led_off, &led_on, &Default::default());
+        assert_eq!(report[4], ChannelStatus::InconsistentWithNeighbours);
+        assert!(!report[4].is_dead());
+        assert_eq!(report[3], ChannelStatus::Ok);
+        assert_eq!(report[5], ChannelStatus::Ok);
+    }
+}
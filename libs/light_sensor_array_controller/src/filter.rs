@@ -0,0 +1,368 @@
+//! Per channel digital filters for the light maps.
+//!
+//! `FilteredLightSensorArray` wraps any `LightSensorArrayController` and passes every channel of
+//! the light maps through its own `ChannelFilter`. The filters keep their state in fixed size
+//! fields (no allocation) and their latency is bounded by their configuration.
+
+use crate::{AcquisitionMode, LightSensorArrayController};
+
+/// A filter for the samples of a single sensor channel.
+pub trait ChannelFilter {
+    /// Feed a new sample and get the filtered value
+    fn filter(&mut self, sample: u16) -> u16;
+
+    /// Forget the past samples
+    fn reset(&mut self);
+}
+
+/// Two filters applied one after the other, e.g. a median to remove spikes and then an
+/// exponential moving average to smooth the result.
+impl<A: ChannelFilter, B: ChannelFilter> ChannelFilter for (A, B) {
+    fn filter(&mut self, sample: u16) -> u16 {
+        let sample = self.0.filter(sample);
+        self.1.filter(sample)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+}
+
+/// Exponential moving average: `output += alpha * (sample - output)`.
+///
+/// `alpha` is given in percent (1 to 100). The lower it is, the smoother and slower the output.
+/// The output is kept in fixed point so small alphas don't get stuck because of the truncation.
+#[derive(Clone, Copy, Debug)]
+pub struct ExponentialMovingAverage {
+    alpha_percent: u8,
+    // output value multiplied by 256, None until the first sample arrives
+    state: Option<u32>,
+}
+
+impl ExponentialMovingAverage {
+    pub fn new(alpha_percent: u8) -> Self {
+        ExponentialMovingAverage {
+            alpha_percent: alpha_percent.clamp(1, 100),
+            state: None,
+        }
+    }
+}
+
+impl ChannelFilter for ExponentialMovingAverage {
+    fn filter(&mut self, sample: u16) -> u16 {
+        let sample = (sample as u32) << 8;
+        let state = match self.state {
+            // The first sample initializes the filter, so there is no ramp up from 0
+            None => sample,
+            Some(state) => {
+                let delta = sample as i32 - state as i32;
+                (state as i32 + delta * self.alpha_percent as i32 / 100) as u32
+            }
+        };
+        self.state = Some(state);
+        ((state + 128) >> 8) as u16
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// Median of the last `N` samples. It removes spikes of up to `N / 2` samples with a
+/// latency of `N / 2` samples. Keep `N` small (3 or 5), the samples are sorted on every call.
+#[derive(Clone, Copy, Debug)]
+pub struct MedianFilter<const N: usize> {
+    samples: [u16; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> MedianFilter<N> {
+    pub fn new() -> Self {
+        const { assert!(N > 0, "the median filter needs at least one sample") };
+        MedianFilter {
+            samples: [0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for MedianFilter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ChannelFilter for MedianFilter<N> {
+    fn filter(&mut self, sample: u16) -> u16 {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+        if self.len < N {
+            self.len += 1;
+        }
+
+        let mut sorted = self.samples;
+        let sorted = &mut sorted[..self.len];
+        sorted.sort_unstable();
+        sorted[self.len / 2]
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+/// Outlier rejection: a sample that jumps more than `max_jump` from the last accepted value is
+/// replaced by that last value. If the jump persists for more than `max_rejections` samples it
+/// is taken as a real change (the robot reached the line) and accepted, so the latency of a step
+/// is bounded to `max_rejections` samples.
+#[derive(Clone, Copy, Debug)]
+pub struct OutlierRejection {
+    max_jump: u16,
+    max_rejections: u8,
+    last: Option<u16>,
+    rejections: u8,
+}
+
+impl OutlierRejection {
+    pub fn new(max_jump: u16, max_rejections: u8) -> Self {
+        OutlierRejection {
+            max_jump,
+            max_rejections,
+            last: None,
+            rejections: 0,
+        }
+    }
+}
+
+impl ChannelFilter for OutlierRejection {
+    fn filter(&mut self, sample: u16) -> u16 {
+        if let Some(last) = self.last {
+            if last.abs_diff(sample) > self.max_jump && self.rejections < self.max_rejections {
+                self.rejections += 1;
+                return last;
+            }
+        }
+        self.rejections = 0;
+        self.last = Some(sample);
+        sample
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+        self.rejections = 0;
+    }
+}
+
+/// A light sensor array whose light maps are filtered channel by channel.
+pub struct FilteredLightSensorArray<C: LightSensorArrayController, F: ChannelFilter> {
+    controller: C,
+    filters: [F; 8],
+}
+
+impl<C: LightSensorArrayController, F: ChannelFilter> FilteredLightSensorArray<C, F> {
+    /// Use a different filter (or filter configuration) for every channel
+    pub fn new(controller: C, filters: [F; 8]) -> Self {
+        FilteredLightSensorArray {
+            controller,
+            filters,
+        }
+    }
+
+    /// Use the same filter configuration for all the channels
+    pub fn with_filter(controller: C, filter: F) -> Self
+    where
+        F: Clone,
+    {
+        Self::new(controller, core::array::from_fn(|_| filter.clone()))
+    }
+
+    /// Forget the past samples of all the channels
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().for_each(|filter| filter.reset());
+    }
+
+    /// Get back the wrapped light sensor array
+    pub fn release(self) -> C {
+        self.controller
+    }
+}
+
+impl<C: LightSensorArrayController, F: ChannelFilter> LightSensorArrayController
+    for FilteredLightSensorArray<C, F>
+{
+    fn get_light_map(&mut self) -> [u16; 8] {
+        let mut light_map = self.controller.get_light_map();
+        for (value, filter) in light_map.iter_mut().zip(self.filters.iter_mut()) {
+            *value = filter.filter(*value);
+        }
+        light_map
+    }
+
+    fn set_led(&mut self, value: bool) {
+        self.controller.set_led(value);
+    }
+
+    fn set_acquisition_mode(&mut self, mode: AcquisitionMode) {
+        // The values of the new mode are not comparable with the old ones
+        self.reset();
+        self.controller.set_acquisition_mode(mode);
+    }
+
+    fn get_acquisition_mode(&self) -> AcquisitionMode {
+        self.controller.get_acquisition_mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Light sensor array that returns the same value in all the channels, taken from a list
+    struct MockLightSensorArray {
+        values: &'static [u16],
+        index: usize,
+    }
+
+    impl MockLightSensorArray {
+        fn new(values: &'static [u16]) -> Self {
+            MockLightSensorArray { values, index: 0 }
+        }
+    }
+
+    impl LightSensorArrayController for MockLightSensorArray {
+        fn get_light_map(&mut self) -> [u16; 8] {
+            let value = self.values[self.index % self.values.len()];
+            self.index += 1;
+            [value; 8]
+        }
+
+        fn set_led(&mut self, _value: bool) {}
+
+        fn set_acquisition_mode(&mut self, _mode: AcquisitionMode) {}
+
+        fn get_acquisition_mode(&self) -> AcquisitionMode {
+            AcquisitionMode::Direct
+        }
+    }
+
+    // Deterministic noise between -range and +range (a linear congruential generator)
+    fn noise(seed: &mut u32, range: i32) -> i32 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        ((*seed >> 16) % (2 * range as u32 + 1)) as i32 - range
+    }
+
+    fn mean_square_error(filter: &mut impl ChannelFilter, level: u16, range: i32) -> (u32, u32) {
+        let mut seed = 1;
+        let (mut input_error, mut output_error) = (0u32, 0u32);
+        for i in 0..200 {
+            let sample = (level as i32 + noise(&mut seed, range)) as u16;
+            let output = filter.filter(sample);
+            // skip the first samples so the filter is settled
+            if i >= 20 {
+                input_error += (sample as i32 - level as i32).pow(2) as u32;
+                output_error += (output as i32 - level as i32).pow(2) as u32;
+            }
+        }
+        (input_error, output_error)
+    }
+
+    #[test]
+    fn test_ema_step_response() {
+        let mut ema = ExponentialMovingAverage::new(50);
+        assert_eq!(ema.filter(1000), 1000);
+        assert_eq!(ema.filter(3000), 2000);
+        assert_eq!(ema.filter(3000), 2500);
+        assert_eq!(ema.filter(3000), 2750);
+
+        // it eventually reaches the step value
+        for _ in 0..20 {
+            ema.filter(3000);
+        }
+        assert_eq!(ema.filter(3000), 3000);
+    }
+
+    #[test]
+    fn test_ema_small_alpha_is_not_stuck() {
+        let mut ema = ExponentialMovingAverage::new(1);
+        ema.filter(1000);
+        for _ in 0..1000 {
+            ema.filter(1100);
+        }
+        assert_eq!(ema.filter(1100), 1100);
+    }
+
+    #[test]
+    fn test_ema_noise_attenuation() {
+        let mut ema = ExponentialMovingAverage::new(20);
+        let (input_error, output_error) = mean_square_error(&mut ema, 2000, 300);
+        assert!(output_error * 4 < input_error);
+    }
+
+    #[test]
+    fn test_median_removes_spikes() {
+        let mut median = MedianFilter::<3>::new();
+        assert_eq!(median.filter(1000), 1000);
+        assert_eq!(median.filter(1010), 1010);
+        assert_eq!(median.filter(4095), 1010);
+        assert_eq!(median.filter(1000), 1010);
+        assert_eq!(median.filter(1005), 1005);
+    }
+
+    #[test]
+    fn test_median_step_latency() {
+        let mut median = MedianFilter::<5>::new();
+        for _ in 0..5 {
+            median.filter(1000);
+        }
+        assert_eq!(median.filter(3000), 1000);
+        assert_eq!(median.filter(3000), 1000);
+        assert_eq!(median.filter(3000), 3000);
+    }
+
+    #[test]
+    fn test_median_noise_attenuation() {
+        let mut median = MedianFilter::<5>::new();
+        let (input_error, output_error) = mean_square_error(&mut median, 2000, 300);
+        assert!(output_error * 2 < input_error);
+    }
+
+    #[test]
+    fn test_outlier_rejection() {
+        let mut outlier = OutlierRejection::new(500, 2);
+        assert_eq!(outlier.filter(1000), 1000);
+        // a single spike is rejected
+        assert_eq!(outlier.filter(4000), 1000);
+        assert_eq!(outlier.filter(1100), 1100);
+        // a step is accepted after max_rejections samples
+        assert_eq!(outlier.filter(3000), 1100);
+        assert_eq!(outlier.filter(3000), 1100);
+        assert_eq!(outlier.filter(3000), 3000);
+        assert_eq!(outlier.filter(3100), 3100);
+    }
+
+    #[test]
+    fn test_chained_filters() {
+        let mut filter = (MedianFilter::<3>::new(), ExponentialMovingAverage::new(50));
+        assert_eq!(filter.filter(1000), 1000);
+        assert_eq!(filter.filter(1000), 1000);
+        assert_eq!(filter.filter(4000), 1000);
+        filter.reset();
+        assert_eq!(filter.filter(2000), 2000);
+    }
+
+    #[test]
+    fn test_filtered_light_sensor_array() {
+        let mock = MockLightSensorArray::new(&[1000, 1000, 4095, 1000, 1000, 3000, 3000]);
+        let mut array = FilteredLightSensorArray::with_filter(mock, MedianFilter::<3>::new());
+
+        // the spike is removed and the step is delayed by one sample
+        for _ in 0..6 {
+            assert_eq!(array.get_light_map(), [1000; 8]);
+        }
+        assert_eq!(array.get_light_map(), [3000; 8]);
+    }
+}
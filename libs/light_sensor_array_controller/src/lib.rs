@@ -1,5 +1,24 @@
 #![no_std]
 
+pub mod adaptive_calibration;
+pub mod calibration;
+pub mod diagnostics;
+pub mod filter;
+pub mod line_position;
+pub mod marker;
+pub mod rc_timing;
+
+/// The way the light maps returned by `LightSensorArrayController::get_light_map` are acquired.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AcquisitionMode {
+    /// A single frame is read with the emitter LED in the state given by `set_led`.
+    Direct,
+    /// A frame is read with the emitter LED off and another one with the LED on, waiting
+    /// `settling_us` microseconds after each LED change. The light map is the difference between
+    /// both frames (see `ambient_rejected_light_map`), so the ambient light is cancelled out.
+    AmbientRejection { settling_us: u32 },
+}
+
 /// The trait implemented by the light sensor arrays, to get a light map with all the values from
 /// the different sensors from it.
 pub trait LightSensorArrayController {
@@ -8,4 +27,59 @@ pub trait LightSensorArrayController {
 
     /// Set led value
     fn set_led(&mut self, value: bool);
+
+    /// Select how the light maps are acquired
+    fn set_acquisition_mode(&mut self, mode: AcquisitionMode);
+
+    /// Get the current acquisition mode
+    fn get_acquisition_mode(&self) -> AcquisitionMode;
+}
+
+/// Combine a frame read with the emitter LED off and a frame read with the LED on.
+///
+/// The sensors output lower values the more light they receive, so `led_off - led_on` is the
+/// light coming from the emitter and reflected by the surface, whatever the ambient light is.
+/// That difference is returned subtracted from `full_scale`, keeping the convention of the raw
+/// light maps: the higher the value, the darker the surface (a black line).
+pub fn ambient_rejected_light_map(
+    led_off: [u16; 8],
+    led_on: [u16; 8],
+    full_scale: u16,
+) -> [u16; 8] {
+    let mut light_map = [0u16; 8];
+    for i in 0..8 {
+        let reflected = led_off[i].saturating_sub(led_on[i]);
+        light_map[i] = full_scale.saturating_sub(reflected);
+    }
+    light_map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ambient_rejection_cancels_ambient_offset() {
+        // the same surface read in the dark and under sunlight (that lowers every value by 900)
+        let dark = ambient_rejected_light_map([4000; 8], [1000; 8], 4095);
+        let sunny = ambient_rejected_light_map([3100; 8], [100; 8], 4095);
+        assert_eq!(dark, [1095; 8]);
+        assert_eq!(sunny, dark);
+    }
+
+    #[test]
+    fn test_ambient_rejection_keeps_black_higher_than_white() {
+        // channel 0 is over the line (little reflected light), channel 1 over the white surface
+        let mut led_off = [3000; 8];
+        let mut led_on = [3000; 8];
+        led_off[0] = 3900;
+        led_on[0] = 3700;
+        led_off[1] = 3500;
+        led_on[1] = 500;
+
+        let light_map = ambient_rejected_light_map(led_off, led_on, 4095);
+        assert_eq!(light_map[0], 3895);
+        assert_eq!(light_map[1], 1095);
+        assert_eq!(light_map[2], 4095);
+    }
 }
@@ -0,0 +1,351 @@
+//! Line position estimation.
+//!
+//! The channel 0 is the rightmost sensor and the channel 7 the leftmost one. The light maps are
+//! expected to be normalized by a `Calibration` (0 is the brightest value seen by a channel, 1000
+//! the darkest one).
+//!
+//! Besides the simple position given by `get_line_position`, `estimate` gives a `LineReading` with
+//! the centroid of the line, the segments of line seen (e.g. both branches of a fork), the width of
+//! the line and a quality score, so the control code can choose a branch and ignore implausible
+//! frames.
+
+use crate::calibration::{Calibration, CALIBRATED_MAX};
+
+/// Distance between the sensors of the QTR-8A array
+pub const SENSOR_PITCH_MM: f32 = 9.525;
+
+/// Raw value over which a sensor sees the line when the sensors are not calibrated
+pub const UNCALIBRATED_THRESHOLD: u16 = 1000;
+
+/// Maximum number of line segments reported by a `LineReading`
+pub const MAX_SEGMENTS: usize = 4;
+
+/// A group of contiguous sensors that see the line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineSegment {
+    /// Position of the segment, from -1 (left) to 1 (right)
+    pub position: f32,
+    /// Estimated width of the segment
+    pub width_mm: f32,
+}
+
+/// Everything the estimator knows about the line in a light map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineReading {
+    /// Position of the centroid of all the segments, from -1 (left) to 1 (right)
+    pub centroid: f32,
+    /// Estimated width of the line, adding up all the segments
+    pub width_mm: f32,
+    /// How plausible the frame is, from 0 to 1: a single segment, with the expected width and a
+    /// good contrast gives a quality close to 1
+    pub quality: f32,
+    segments: [LineSegment; MAX_SEGMENTS],
+    segment_count: usize,
+}
+
+impl LineReading {
+    /// The segments of line seen, from right to left
+    pub fn segments(&self) -> &[LineSegment] {
+        &self.segments[..self.segment_count]
+    }
+
+    /// The segment closest to a position, e.g. to keep following the same branch of a fork
+    pub fn closest_segment(&self, position: f32) -> LineSegment {
+        let mut closest = self.segments[0];
+        for segment in self.segments() {
+            if abs(segment.position - position) < abs(closest.position - position) {
+                closest = *segment;
+            }
+        }
+        closest
+    }
+}
+
+/// The kind of line of the track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinePolarity {
+    /// A dark (black) line on a light (white) surface
+    DarkLine,
+    /// A light (white) line on a dark (black) surface
+    LightLine,
+}
+
+pub struct LinePositionEstimator {
+    pub polarity: LinePolarity,
+    /// Minimum value of the channel over the line to consider that the line is found
+    pub threshold: u16,
+    /// Expected width of the line (20 mm in most competitions)
+    pub line_width_mm: f32,
+}
+
+impl LinePositionEstimator {
+    pub fn new(polarity: LinePolarity) -> Self {
+        LinePositionEstimator {
+            polarity,
+            threshold: CALIBRATED_MAX / 2,
+            line_width_mm: 20.,
+        }
+    }
+
+    /// An estimator for the light maps normalized with `calibration`. Without a calibration (the
+    /// default one), the line is found over the raw `UNCALIBRATED_THRESHOLD`, as before the
+    /// calibration existed, instead of over the middle of the 12 bits range.
+    pub fn with_calibration(calibration: &Calibration) -> Self {
+        let mut estimator = Self::new(calibration.polarity);
+        if *calibration == Calibration::default() {
+            estimator.threshold = calibration.normalize(&[UNCALIBRATED_THRESHOLD; 8])[0];
+        }
+        estimator
+    }
+
+    /// Get a `LineReading` from a normalized light map, or None if no line is detected.
+    pub fn estimate(&self, light_map: &[u16; 8]) -> Option<LineReading> {
+        let line_sensor = self.line_intensity(light_map);
+
+        let mut reading = LineReading {
+            centroid: 0.,
+            width_mm: 0.,
+            quality: 0.,
+            segments: [LineSegment {
+                position: 0.,
+                width_mm: 0.,
+            }; MAX_SEGMENTS],
+            segment_count: 0,
+        };
+        let (mut total_weight, mut total_moment) = (0u32, 0f32);
+        let mut max_value = 0;
+        // First channel not taken by a segment yet
+        let mut next_free = 0;
+
+        let mut i = 0;
+        while i < 8 {
+            if line_sensor[i] < self.threshold {
+                i += 1;
+                continue;
+            }
+            let first = i;
+            while i < 7 && line_sensor[i + 1] >= self.threshold {
+                i += 1;
+            }
+            let last = i;
+            i += 1;
+
+            // The neighbour sensors are included, they are partially over the line. A neighbour
+            // between two segments is only added to the first one, so it is not counted twice.
+            let (mut weight, mut moment) = (0u32, 0f32);
+            let (from, to) = (first.saturating_sub(1).max(next_free), (last + 1).min(7));
+            next_free = to + 1;
+            for (channel, &value) in line_sensor.iter().enumerate().take(to + 1).skip(from) {
+                weight += value as u32;
+                moment += value as f32 * channel_position(channel);
+                max_value = max_value.max(value);
+            }
+            total_weight += weight;
+            total_moment += moment;
+
+            if reading.segment_count < MAX_SEGMENTS {
+                reading.segments[reading.segment_count] = LineSegment {
+                    position: moment / weight as f32,
+                    width_mm: weight as f32 * SENSOR_PITCH_MM / CALIBRATED_MAX as f32,
+                };
+                reading.segment_count += 1;
+            }
+        }
+
+        if reading.segment_count == 0 {
+            return None;
+        }
+
+        reading.centroid = total_moment / total_weight as f32;
+        reading.width_mm = total_weight as f32 * SENSOR_PITCH_MM / CALIBRATED_MAX as f32;
+
+        let contrast = max_value as f32 / CALIBRATED_MAX as f32;
+        let width_error = abs(reading.width_mm - self.line_width_mm) / self.line_width_mm;
+        let width_plausibility = (1. - width_error).max(0.);
+        reading.quality = contrast * width_plausibility / reading.segment_count as f32;
+
+        Some(reading)
+    }
+
+    /// Get the line position from a normalized light map, a value between -1 and 1, where -1 means
+    /// the line is on the left, 0 means the line is in the middle, and 1 means the line is on the
+    /// right. The position is the one of the sensor that sees the line the most (the highest value
+    /// for a dark line, the lowest one for a light line).
+    /// If no line is detected, the function returns None.
+    pub fn get_line_position(&self, light_map: &[u16; 8]) -> Option<f32> {
+        let line_sensor = self.line_intensity(light_map);
+
+        let mut max_value = line_sensor[0];
+        let mut max_index = 0;
+        for (i, &value) in line_sensor.iter().enumerate().skip(1) {
+            if value > max_value {
+                max_value = value;
+                max_index = i;
+            }
+        }
+
+        if max_value < self.threshold {
+            return None;
+        }
+
+        match max_index {
+            0 => Some(1.), // line is on the right
+            1 => Some(0.75),
+            2 => Some(0.5),
+            3 => Some(0.0),
+            4 => Some(0.0),
+            5 => Some(-0.5),
+            6 => Some(-0.75),
+            7 => Some(-1.), // line is on the left
+            _ => None,
+        }
+    }
+
+    /// Turn a normalized light map into how much each sensor sees the line (0 nothing, 1000 the
+    /// sensor is completely over the line), whatever the polarity of the line is.
+    pub fn line_intensity(&self, light_map: &[u16; 8]) -> [u16; 8] {
+        match self.polarity {
+            LinePolarity::DarkLine => light_map.map(|value| value.min(CALIBRATED_MAX)),
+            LinePolarity::LightLine => {
+                light_map.map(|value| CALIBRATED_MAX - value.min(CALIBRATED_MAX))
+            }
+        }
+    }
+}
+
+// Position of a sensor, from -1 (channel 7, left) to 1 (channel 0, right)
+fn channel_position(channel: usize) -> f32 {
+    (3.5 - channel as f32) / 3.5
+}
+
+fn abs(value: f32) -> f32 {
+    if value < 0. {
+        -value
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dark_line_position() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let light_map = [0, 0, 0, 900, 800, 0, 0, 0];
+        assert_eq!(estimator.get_line_position(&light_map), Some(0.));
+        let light_map = [950, 300, 0, 0, 0, 0, 0, 0];
+        assert_eq!(estimator.get_line_position(&light_map), Some(1.));
+        let light_map = [0, 0, 0, 0, 0, 0, 300, 700];
+        assert_eq!(estimator.get_line_position(&light_map), Some(-1.));
+    }
+
+    #[test]
+    fn test_dark_line_not_found() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        assert_eq!(estimator.get_line_position(&[100; 8]), None);
+    }
+
+    #[test]
+    fn test_uncalibrated_threshold() {
+        let calibration = Calibration::default();
+        let estimator = LinePositionEstimator::with_calibration(&calibration);
+        let light_map = [300, 300, 300, 1100, 300, 300, 300, 300];
+        let position = estimator.get_line_position(&calibration.normalize(&light_map));
+        assert_eq!(position, Some(0.));
+        let light_map = [300, 300, 300, 900, 300, 300, 300, 300];
+        let position = estimator.get_line_position(&calibration.normalize(&light_map));
+        assert_eq!(position, None);
+
+        let calibration = Calibration {
+            min: [200; 8],
+            max: [3000; 8],
+            ..Default::default()
+        };
+        let estimator = LinePositionEstimator::with_calibration(&calibration);
+        assert_eq!(estimator.threshold, CALIBRATED_MAX / 2);
+    }
+
+    #[test]
+    fn test_light_line_position() {
+        let estimator = LinePositionEstimator::new(LinePolarity::LightLine);
+        let light_map = [1000, 1000, 50, 900, 1000, 1000, 1000, 1000];
+        assert_eq!(estimator.get_line_position(&light_map), Some(0.5));
+        // a dark surface without line
+        assert_eq!(estimator.get_line_position(&[950; 8]), None);
+        // with the wrong polarity, the dark surface is taken as the line
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        assert_eq!(estimator.get_line_position(&light_map), Some(1.));
+    }
+
+    #[test]
+    fn test_centered_thin_line() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator
+            .estimate(&[0, 0, 100, 1000, 1000, 100, 0, 0])
+            .unwrap();
+        assert!(abs(reading.centroid) < 0.001);
+        assert_eq!(reading.segments().len(), 1);
+        // two sensors completely over the line and two neighbours a bit over it
+        assert!(abs(reading.width_mm - 2.2 * SENSOR_PITCH_MM) < 0.01);
+        assert!(reading.quality > 0.9);
+    }
+
+    #[test]
+    fn test_off_center_line() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator
+            .estimate(&[0, 500, 1000, 500, 0, 0, 0, 0])
+            .unwrap();
+        assert!(abs(reading.centroid - channel_position(2)) < 0.001);
+        // the light line gives the same reading
+        let estimator = LinePositionEstimator::new(LinePolarity::LightLine);
+        let light_line = estimator.estimate(&[1000, 500, 0, 500, 1000, 1000, 1000, 1000]);
+        assert_eq!(light_line, Some(reading));
+    }
+
+    #[test]
+    fn test_wide_patch_has_low_quality() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator
+            .estimate(&[0, 900, 1000, 1000, 1000, 1000, 900, 0])
+            .unwrap();
+        assert_eq!(reading.segments().len(), 1);
+        assert!(reading.width_mm > 50.);
+        assert!(reading.quality < 0.1);
+    }
+
+    #[test]
+    fn test_fork() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator
+            .estimate(&[1000, 900, 0, 0, 0, 0, 900, 1000])
+            .unwrap();
+        assert_eq!(reading.segments().len(), 2);
+        assert!(abs(reading.centroid) < 0.001);
+        assert!(reading.segments()[0].position > 0.5);
+        assert!(reading.segments()[1].position < -0.5);
+        assert!(reading.quality < 0.5);
+        // keep following the left branch
+        assert_eq!(reading.closest_segment(-0.3), reading.segments()[1]);
+    }
+
+    #[test]
+    fn test_segments_sharing_a_neighbour() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        let reading = estimator
+            .estimate(&[1000, 1000, 400, 1000, 1000, 0, 0, 0])
+            .unwrap();
+        assert_eq!(reading.segments().len(), 2);
+        // the channel between the segments is counted once
+        assert!(abs(reading.width_mm - 4.4 * SENSOR_PITCH_MM) < 0.01);
+        assert!(abs(reading.centroid - channel_position(2)) < 0.001);
+    }
+
+    #[test]
+    fn test_estimate_no_line() {
+        let estimator = LinePositionEstimator::new(LinePolarity::DarkLine);
+        assert_eq!(estimator.estimate(&[100; 8]), None);
+    }
+}
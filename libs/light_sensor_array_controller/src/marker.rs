@@ -0,0 +1,346 @@
+//! Track marker detection.
+//!
+//! Besides the line, the tracks can have side marks (e.g. start/finish or curve marks) and lines
+//! crossing the track. The `MarkerDetector` classifies the light maps over the distance run by the
+//! robot and produces a `MarkerEvent` once the robot has passed a marker.
+//!
+//! The channel 0 is the rightmost sensor and the channel 7 the leftmost one, as in the line
+//! position estimation of the line follower.
+
+/// The kind of marker found on the track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarkerKind {
+    /// A mark at the left side of the line
+    Left,
+    /// A mark at the right side of the line
+    Right,
+    /// A line crossing the whole sensor array (e.g. start/finish line)
+    CrossingLine,
+    /// Marks at both sides of the line, but not crossing the whole array at once
+    Intersection,
+}
+
+/// A marker that the robot has passed over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MarkerEvent {
+    pub kind: MarkerKind,
+    /// Distance (odometer) where the marker was first seen
+    pub start_mm: u32,
+    /// Distance between the first and the last frame where the marker was seen
+    pub length_mm: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MarkerDetectorConfig {
+    /// Light map values above this threshold are dark (line or mark)
+    pub dark_threshold: u16,
+    /// Number of channels at each end of the array where the side marks are looked for
+    pub side_channels: usize,
+    /// The widest the line can be, in channels. Wider dark segments include a mark.
+    pub max_line_channels: usize,
+    /// Number of dark channels to consider that a line crosses the array
+    pub crossing_channels: usize,
+    /// Distance without marks needed to finish a marker, so a long or patchy marker (or a marker
+    /// seen while the robot stops over it) is counted only once
+    pub clear_distance_mm: u32,
+    /// Markers seen in fewer frames are discarded as noise
+    pub min_frames: u16,
+}
+
+impl Default for MarkerDetectorConfig {
+    fn default() -> Self {
+        MarkerDetectorConfig {
+            dark_threshold: 1000,
+            side_channels: 2,
+            max_line_channels: 3,
+            crossing_channels: 7,
+            clear_distance_mm: 30,
+            min_frames: 1,
+        }
+    }
+}
+
+// The marks seen in a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct FramePattern {
+    left: bool,
+    right: bool,
+    crossing: bool,
+}
+
+impl FramePattern {
+    fn any(&self) -> bool {
+        self.left || self.right || self.crossing
+    }
+}
+
+// The marker that is being passed over
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    start_mm: u32,
+    last_seen_mm: u32,
+    frames: u16,
+    pattern: FramePattern,
+}
+
+pub struct MarkerDetector {
+    config: MarkerDetectorConfig,
+    span: Option<Span>,
+}
+
+impl MarkerDetector {
+    pub fn new(config: MarkerDetectorConfig) -> Self {
+        MarkerDetector { config, span: None }
+    }
+
+    /// Forget the marker being passed over (e.g. when the robot is lifted from the track)
+    pub fn reset(&mut self) {
+        self.span = None;
+    }
+
+    /// Feed a light map and the distance run by the robot when it was read. It returns an event
+    /// when a marker has been left behind. The distance goes backwards while the robot reverses,
+    /// and a marker is not left behind until the robot moves forward past it again.
+    pub fn update(&mut self, light_map: &[u16; 8], distance_mm: u32) -> Option<MarkerEvent> {
+        let pattern = self.classify(light_map);
+
+        if pattern.any() {
+            let span = self.span.get_or_insert(Span {
+                start_mm: distance_mm,
+                last_seen_mm: distance_mm,
+                frames: 0,
+                pattern: FramePattern::default(),
+            });
+            if distance_mm.wrapping_sub(span.last_seen_mm) as i32 > 0 {
+                span.last_seen_mm = distance_mm;
+            }
+            span.frames = span.frames.saturating_add(1);
+            span.pattern.left |= pattern.left;
+            span.pattern.right |= pattern.right;
+            span.pattern.crossing |= pattern.crossing;
+            return None;
+        }
+
+        let span = self.span?;
+        // Negative while the robot is behind the last place where the marker was seen
+        let clear_mm = distance_mm.wrapping_sub(span.last_seen_mm) as i32;
+        if clear_mm < self.config.clear_distance_mm as i32 {
+            return None;
+        }
+        self.span = None;
+
+        if span.frames < self.config.min_frames {
+            return None;
+        }
+        let kind = match span.pattern {
+            FramePattern { crossing: true, .. } => MarkerKind::CrossingLine,
+            FramePattern {
+                left: true,
+                right: true,
+                ..
+            } => MarkerKind::Intersection,
+            FramePattern { left: true, .. } => MarkerKind::Left,
+            _ => MarkerKind::Right,
+        };
+        Some(MarkerEvent {
+            kind,
+            start_mm: span.start_mm,
+            length_mm: span.last_seen_mm.wrapping_sub(span.start_mm),
+        })
+    }
+
+    fn classify(&self, light_map: &[u16; 8]) -> FramePattern {
+        let dark = light_map.map(|value| value > self.config.dark_threshold);
+        let dark_count = dark.iter().filter(|&&d| d).count();
+        if dark_count >= self.config.crossing_channels {
+            return FramePattern {
+                crossing: true,
+                ..Default::default()
+            };
+        }
+
+        // Split the dark channels in segments (first channel, last channel)
+        let mut segments = [(0usize, 0usize); 4];
+        let mut segment_count = 0;
+        let mut i = 0;
+        while i < 8 {
+            if dark[i] {
+                let first = i;
+                while i < 7 && dark[i + 1] {
+                    i += 1;
+                }
+                if segment_count < segments.len() {
+                    segments[segment_count] = (first, i);
+                    segment_count += 1;
+                }
+            }
+            i += 1;
+        }
+        let segments = &segments[..segment_count];
+
+        // A segment reaching an end of the array is a mark if there is also a line somewhere
+        // else, or if it is too wide to be the line alone
+        let is_mark = |(first, last): (usize, usize)| {
+            segments.len() > 1 || last - first + 1 > self.config.max_line_channels
+        };
+        let side = self.config.side_channels;
+        FramePattern {
+            right: segments
+                .iter()
+                .any(|&segment| segment.0 < side && is_mark(segment)),
+            left: segments
+                .iter()
+                .any(|&segment| segment.1 >= 8 - side && is_mark(segment)),
+            crossing: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const B: u16 = 3000; // black
+    const W: u16 = 200; // white
+
+    const LINE: [u16; 8] = [W, W, W, B, B, W, W, W];
+    const LEFT_MARK: [u16; 8] = [W, W, W, B, B, W, B, B];
+    const LEFT_MARK_TOUCHING: [u16; 8] = [W, W, W, B, B, B, B, B];
+    const RIGHT_MARK: [u16; 8] = [B, B, W, B, B, W, W, W];
+    const BOTH_MARKS: [u16; 8] = [B, W, W, B, B, W, W, B];
+    const CROSSING: [u16; 8] = [B, B, B, B, B, B, B, B];
+    const DRIFTED_LINE: [u16; 8] = [W, W, W, W, W, W, B, B];
+
+    fn run(detector: &mut MarkerDetector, frames: &[([u16; 8], u32)]) -> Option<MarkerEvent> {
+        let mut event = None;
+        for (light_map, distance_mm) in frames {
+            if let Some(e) = detector.update(light_map, *distance_mm) {
+                assert!(event.is_none(), "more than one event");
+                event = Some(e);
+            }
+        }
+        event
+    }
+
+    #[test]
+    fn test_no_marker_on_plain_line() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let frames = [(LINE, 0), (LINE, 10), (DRIFTED_LINE, 20), (LINE, 100)];
+        assert_eq!(run(&mut detector, &frames), None);
+    }
+
+    #[test]
+    fn test_left_marker() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let frames = [
+            (LINE, 0),
+            (LEFT_MARK, 10),
+            (LEFT_MARK_TOUCHING, 20),
+            (LINE, 30),
+            (LINE, 60),
+        ];
+        assert_eq!(
+            run(&mut detector, &frames),
+            Some(MarkerEvent {
+                kind: MarkerKind::Left,
+                start_mm: 10,
+                length_mm: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_right_marker() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let frames = [(RIGHT_MARK, 100), (LINE, 200)];
+        let event = run(&mut detector, &frames).unwrap();
+        assert_eq!(event.kind, MarkerKind::Right);
+    }
+
+    #[test]
+    fn test_crossing_line() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let frames = [(LINE, 0), (CROSSING, 10), (LEFT_MARK, 15), (LINE, 50)];
+        let event = run(&mut detector, &frames).unwrap();
+        assert_eq!(event.kind, MarkerKind::CrossingLine);
+    }
+
+    #[test]
+    fn test_intersection() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let frames = [
+            (LEFT_MARK, 0),
+            (BOTH_MARKS, 5),
+            (RIGHT_MARK, 10),
+            (LINE, 50),
+        ];
+        let event = run(&mut detector, &frames).unwrap();
+        assert_eq!(event.kind, MarkerKind::Intersection);
+    }
+
+    #[test]
+    fn test_long_marker_is_counted_once() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let mut events = 0;
+        // a 200 mm long mark, with a couple of frames where it is not seen
+        for distance_mm in (0..400).step_by(10) {
+            let light_map = match distance_mm {
+                100..=300 if distance_mm != 150 && distance_mm != 160 => LEFT_MARK,
+                _ => LINE,
+            };
+            if let Some(event) = detector.update(&light_map, distance_mm) {
+                assert_eq!(event.kind, MarkerKind::Left);
+                assert_eq!(event.length_mm, 200);
+                events += 1;
+            }
+        }
+        assert_eq!(events, 1);
+    }
+
+    #[test]
+    fn test_stopped_over_marker_is_counted_once() {
+        let mut detector = MarkerDetector::new(Default::default());
+        let mut frames = [(LEFT_MARK, 40); 20];
+        frames[19] = (LINE, 100);
+        assert!(run(&mut detector, &frames).is_some());
+    }
+
+    #[test]
+    fn test_reversing_over_marker() {
+        let mut detector = MarkerDetector::new(Default::default());
+        // the robot goes back over the mark and then forward again
+        let frames = [
+            (LEFT_MARK, 100),
+            (LEFT_MARK, 110),
+            (LINE, 90),
+            (LEFT_MARK, 95),
+            (LINE, 50),
+            (LINE, 120),
+        ];
+        assert_eq!(run(&mut detector, &frames), None);
+        assert_eq!(
+            detector.update(&LINE, 140),
+            Some(MarkerEvent {
+                kind: MarkerKind::Left,
+                start_mm: 100,
+                length_mm: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_short_noise_is_discarded() {
+        let mut detector = MarkerDetector::new(MarkerDetectorConfig {
+            min_frames: 2,
+            ..Default::default()
+        });
+        let frames = [
+            (RIGHT_MARK, 0),
+            (LINE, 40),
+            (RIGHT_MARK, 50),
+            (RIGHT_MARK, 60),
+        ];
+        assert_eq!(run(&mut detector, &frames), None);
+        assert!(detector.update(&LINE, 100).is_some());
+    }
+}
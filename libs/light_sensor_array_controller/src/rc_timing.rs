@@ -0,0 +1,90 @@
+//! Discharge timing of RC (QTR-8RC like) sensor arrays.
+//!
+//! Each sensor of an RC array is a capacitor discharged by a phototransistor: the lines are charged
+//! driving them high, then switched to inputs and the time until each line reads low is measured.
+//! The more light a sensor receives, the faster its line discharges. `RcDischargeMeasurement`
+//! collects the line levels polled while they discharge and turns the discharge times into a light
+//! map with the same convention as the analog arrays: the higher the value, the darker the surface.
+
+pub struct RcDischargeMeasurement {
+    timeout_cycles: u32,
+    // Discharge time of each line, the timeout if it has not discharged yet
+    discharge_cycles: [u32; 8],
+    // Bit mask of the lines that have not discharged yet
+    pending: u8,
+}
+
+impl RcDischargeMeasurement {
+    /// Start a measurement, just after the lines have been switched to inputs. The time is given
+    /// in any unit (e.g. core clock cycles) as long as it is the same for all the calls.
+    pub fn new(timeout_cycles: u32) -> Self {
+        RcDischargeMeasurement {
+            timeout_cycles,
+            discharge_cycles: [timeout_cycles; 8],
+            pending: 0xFF,
+        }
+    }
+
+    /// Record the levels of the lines (bit `i` set if the line `i` is still high) read
+    /// `elapsed_cycles` after the measurement started. It returns true while some line has not
+    /// discharged and the timeout has not expired, so it can be used as the condition of the
+    /// polling loop.
+    pub fn sample(&mut self, elapsed_cycles: u32, levels: u8) -> bool {
+        let elapsed_cycles = elapsed_cycles.min(self.timeout_cycles);
+        let discharged = self.pending & !levels;
+        for (i, discharge_cycles) in self.discharge_cycles.iter_mut().enumerate() {
+            if discharged & (1 << i) != 0 {
+                *discharge_cycles = elapsed_cycles;
+            }
+        }
+        self.pending &= levels;
+
+        self.pending != 0 && elapsed_cycles < self.timeout_cycles
+    }
+
+    /// Get the light map, scaling the discharge times from 0 to `full_scale` (the timeout)
+    pub fn light_map(&self, full_scale: u16) -> [u16; 8] {
+        let timeout_cycles = self.timeout_cycles.max(1) as u64;
+        self.discharge_cycles
+            .map(|cycles| (cycles as u64 * full_scale as u64 / timeout_cycles) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discharge_times() {
+        let mut measurement = RcDischargeMeasurement::new(1000);
+        assert!(measurement.sample(0, 0b1111_1111));
+        assert!(measurement.sample(100, 0b1111_1110));
+        assert!(measurement.sample(250, 0b0111_1100));
+        assert!(measurement.sample(500, 0b0001_0000));
+        assert!(!measurement.sample(750, 0b0000_0000));
+
+        assert_eq!(
+            measurement.light_map(4000),
+            [400, 1000, 2000, 2000, 3000, 2000, 2000, 1000]
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut measurement = RcDischargeMeasurement::new(1000);
+        assert!(measurement.sample(500, 0b1000_0000));
+        // a line that never discharges (very dark surface) reads the full scale
+        assert!(!measurement.sample(1200, 0b1000_0000));
+        assert_eq!(measurement.light_map(4095)[7], 4095);
+        assert_eq!(measurement.light_map(4095)[0], 2047);
+    }
+
+    #[test]
+    fn test_glitches_after_discharge_are_ignored() {
+        let mut measurement = RcDischargeMeasurement::new(1000);
+        measurement.sample(100, 0b1111_1110);
+        // the line 0 reads high again because of noise, it keeps its discharge time
+        measurement.sample(200, 0b1111_1111);
+        assert_eq!(measurement.light_map(1000)[0], 100);
+    }
+}
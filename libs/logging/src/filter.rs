@@ -0,0 +1,140 @@
+// Severity levels and filtering of the log records.
+//
+// The filter is shared by all the loggers of the application and can be changed at runtime through
+// a shared reference (it uses cells), so the loggers can borrow it while it is changed. It has a
+// maximum level for every record and, optionally, a different maximum level for some tags (the
+// source of the records), so a noisy part of the application can be silenced or a single one can be
+// debugged.
+
+use core::cell::Cell;
+
+/// Severity of a record, from the most to the least severe. `Off` is only used in the filters, to
+/// silence everything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "OFF",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Number of tags that can have their own level
+pub const MAX_TAG_LEVELS: usize = 4;
+
+pub struct LogFilter {
+    max_level: Cell<Level>,
+    tag_levels: Cell<[Option<(&'static str, Level)>; MAX_TAG_LEVELS]>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(Level::Info)
+    }
+}
+
+impl LogFilter {
+    pub const fn new(max_level: Level) -> Self {
+        LogFilter {
+            max_level: Cell::new(max_level),
+            tag_levels: Cell::new([None; MAX_TAG_LEVELS]),
+        }
+    }
+
+    pub fn max_level(&self) -> Level {
+        self.max_level.get()
+    }
+
+    /// Show the records up to `max_level`, except for the tags with their own level
+    pub fn set_max_level(&self, max_level: Level) {
+        self.max_level.set(max_level);
+    }
+
+    /// Show the records of `tag` up to `max_level`, whatever the level of the rest is. It returns
+    /// false if there are already MAX_TAG_LEVELS tags with their own level.
+    pub fn set_tag_level(&self, tag: &'static str, max_level: Level) -> bool {
+        let mut tag_levels = self.tag_levels.get();
+        // The entry of the tag, or a free one
+        let slot = tag_levels
+            .iter()
+            .position(|entry| entry.is_some_and(|(t, _)| t == tag))
+            .or_else(|| tag_levels.iter().position(Option::is_none));
+        let Some(slot) = slot else {
+            return false;
+        };
+        tag_levels[slot] = Some((tag, max_level));
+        self.tag_levels.set(tag_levels);
+        true
+    }
+
+    /// Remove the level of every tag, so all the records use the maximum level
+    pub fn clear_tag_levels(&self) {
+        self.tag_levels.set([None; MAX_TAG_LEVELS]);
+    }
+
+    /// Whether a record of `level` from `tag` must be shown
+    pub fn is_enabled(&self, tag: Option<&str>, level: Level) -> bool {
+        let tag_level = tag.and_then(|tag| {
+            self.tag_levels
+                .get()
+                .into_iter()
+                .flatten()
+                .find(|&(t, _)| t == tag)
+                .map(|(_, level)| level)
+        });
+        level != Level::Off && level <= tag_level.unwrap_or(self.max_level.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let filter = LogFilter::default();
+        assert!(filter.is_enabled(None, Level::Error));
+        assert!(filter.is_enabled(Some("idle"), Level::Info));
+        assert!(!filter.is_enabled(Some("idle"), Level::Debug));
+
+        filter.set_max_level(Level::Off);
+        assert!(!filter.is_enabled(None, Level::Error));
+        assert!(!filter.is_enabled(None, Level::Off));
+    }
+
+    #[test]
+    fn test_tag_levels() {
+        let filter = LogFilter::new(Level::Warn);
+        assert!(filter.set_tag_level("idle", Level::Off));
+        assert!(filter.set_tag_level("line_following", Level::Debug));
+        assert!(!filter.is_enabled(Some("idle"), Level::Error));
+        assert!(filter.is_enabled(Some("line_following"), Level::Debug));
+        assert!(!filter.is_enabled(Some("calibration"), Level::Info));
+
+        // the level of a tag is replaced, not added again
+        for _ in 0..MAX_TAG_LEVELS {
+            assert!(filter.set_tag_level("idle", Level::Trace));
+        }
+        assert!(filter.is_enabled(Some("idle"), Level::Trace));
+        assert!(filter.set_tag_level("a", Level::Off));
+        assert!(filter.set_tag_level("b", Level::Off));
+        assert!(!filter.set_tag_level("c", Level::Off));
+
+        filter.clear_tag_levels();
+        assert!(!filter.is_enabled(Some("idle"), Level::Info));
+    }
+}
@@ -1,31 +1,92 @@
 // A simple logging library for embedded Rust
+//
+// Besides the plain strings and numbers, the logger implements core::fmt::Write, so anything can be
+// formatted without allocation with the log! and logln! macros (signed integers, hex, fixed point
+// numbers with Fixed, floats...):
+//
+//     logln!(logger, "delta: {}, status: {:#06x}, battery: {} V", -12, 0x2a, Fixed::new(7412, 3));
+//
+// The formatting of floats takes quite a lot of flash, so fixed point numbers are preferred.
+//
+// The records (a line with a severity level) are logged with the error!, warn!, info!, debug! and
+// trace! macros. A logger can have a tag (the source of its records), a filter shared with the
+// other loggers (see filter) and a clock to timestamp the records:
+//
+//     let mut logger = Logger::new(&mut tx).tagged("idle").filtered(&filter).timestamped(&clock);
+//     warn!(logger, "Battery at {} %", 15);  // [12.345] WARN idle: Battery at 15 %
+//
+// The plain logs (log, log_u16, log!...) are not filtered.
 #![no_std]
 extern crate alloc;
 
 use core::convert::Infallible;
+use core::fmt;
 
 use embedded_hal::blocking::serial::Write;
 use nb::block;
 
+pub mod filter;
+pub use filter::{Level, LogFilter};
+
+// A clock to timestamp the records
+pub trait LogClock {
+    // Milliseconds since any moment (usually the boot)
+    fn now_ms(&self) -> u32;
+}
+
 pub struct Logger<'a> {
     pub uart: &'a mut dyn Write<u8, Error = Infallible>,
+    tag: Option<&'static str>,
+    filter: Option<&'a LogFilter>,
+    clock: Option<&'a dyn LogClock>,
 }
 
 impl<'a> Logger<'a> {
     pub fn new(uart: &'a mut dyn Write<u8, Error = Infallible>) -> Self {
-        Logger { uart }
+        Logger {
+            uart,
+            tag: None,
+            filter: None,
+            clock: None,
+        }
     }
 
-    pub fn log(&mut self, s: &str) {
-        for c in s.chars() {
-            block!(match self.uart.bwrite_all(&[c as u8]) {
-                Ok(_) => Ok(()),
-                Err(_) => Err(nb::Error::Other(())),
-            })
-            .unwrap();
+    // Add `tag` to the records, it can be used to filter them
+    pub fn tagged(mut self, tag: &'static str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    // Only log the records accepted by `filter` (without a filter, all of them are logged)
+    pub fn filtered(mut self, filter: &'a LogFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    // Add the time of `clock` to the records
+    pub fn timestamped(mut self, clock: &'a dyn LogClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    // This function returns true if the records of `level` are logged
+    pub fn is_enabled(&self, level: Level) -> bool {
+        match self.filter {
+            Some(filter) => filter.is_enabled(self.tag, level),
+            None => level != Level::Off,
         }
     }
 
+    // This function writes the string in a single write, so on a buffered port (like the one of
+    // the board) it doesn't wait for the string to be sent
+    pub fn log(&mut self, s: &str) {
+        block!(match self.uart.bwrite_all(s.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(nb::Error::Other(())),
+        })
+        .unwrap();
+    }
+
     pub fn log_u16(&mut self, val: &u16) {
         for digit_index in (1..6).rev() {
 
@@ -55,6 +116,128 @@ impl<'a> Logger<'a> {
 
         self.log("]");
     }
+
+    // This function logs formatted arguments, use the log! and logln! macros instead
+    pub fn log_fmt(&mut self, args: fmt::Arguments) {
+        // Logging never fails, so formatting only fails if a Display implementation fails
+        let _ = fmt::Write::write_fmt(self, args);
+    }
+
+    // This function logs a record (a line with the time, the level and the tag) if the filter
+    // accepts it, use the error!, warn!, info!, debug! and trace! macros instead
+    pub fn log_record(&mut self, level: Level, args: fmt::Arguments) {
+        if !self.is_enabled(level) {
+            return;
+        }
+        if let Some(clock) = self.clock {
+            let now_ms = clock.now_ms();
+            self.log_fmt(format_args!("[{}.{:03}] ", now_ms / 1000, now_ms % 1000));
+        }
+        self.log(level.name());
+        self.log(" ");
+        if let Some(tag) = self.tag {
+            self.log(tag);
+            self.log(": ");
+        }
+        self.log_fmt(args);
+        self.log("\r\n");
+    }
+}
+
+impl fmt::Write for Logger<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.log(s);
+        Ok(())
+    }
+}
+
+// Log formatted text, as format! does: log!(logger, "x: {}", x)
+#[macro_export]
+macro_rules! log {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_fmt(core::format_args!($($arg)*))
+    };
+}
+
+// Log formatted text followed by a new line ("\r\n")
+#[macro_export]
+macro_rules! logln {
+    ($logger:expr) => {
+        $logger.log("\r\n")
+    };
+    ($logger:expr, $($arg:tt)*) => {{
+        $logger.log_fmt(core::format_args!($($arg)*));
+        $logger.log("\r\n");
+    }};
+}
+
+// Log a record of a level: error!(logger, "Sensor {} is dead", 3)
+#[macro_export]
+macro_rules! error {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_record($crate::Level::Error, core::format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_record($crate::Level::Warn, core::format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_record($crate::Level::Info, core::format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_record($crate::Level::Debug, core::format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log_record($crate::Level::Trace, core::format_args!($($arg)*))
+    };
+}
+
+// A fixed point number: `value` in units of 10^-`decimals`. For example, Fixed::new(1234, 3) is
+// 1.234 (millivolts shown as volts). Up to MAX_FIXED_DECIMALS decimals are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed {
+    pub value: i32,
+    pub decimals: u8,
+}
+
+// 10^19 is the largest power of ten that fits in the u64 scale
+pub const MAX_FIXED_DECIMALS: u8 = 19;
+
+impl Fixed {
+    pub const fn new(value: i32, decimals: u8) -> Self {
+        Fixed { value, decimals }
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = self.decimals.min(MAX_FIXED_DECIMALS);
+        let scale = 10u64.pow(decimals as u32);
+        let abs = self.value.unsigned_abs() as u64;
+        if self.value < 0 {
+            f.write_str("-")?;
+        }
+        write!(f, "{}", abs / scale)?;
+        if decimals > 0 {
+            write!(f, ".{:0width$}", abs % scale, width = decimals as usize)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -63,7 +246,7 @@ mod tests {
 
     use core::convert::Infallible;
 
-    use crate::Logger;
+    use crate::{Fixed, Level, LogClock, LogFilter, Logger};
 
     // mock of embedded_hal::blocking::serial::Write that just writes to a char vector
     struct MockWriter {
@@ -124,4 +307,116 @@ mod tests {
         logger.log_u16_array(&[100u16, 250u16, 500u16]);
         assert_eq!(mock_writer.get_string(), "[00100, 00250, 00500]");
     }
+
+    #[test]
+    fn test_fmt_write() {
+        use core::fmt::Write;
+
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer);
+        write!(logger, "{} {:x} {:#06X}", -42i32, 255u8, 0x2au16).unwrap();
+        assert_eq!(mock_writer.get_string(), "-42 ff 0x002A");
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer);
+        log!(logger, "steps: {}", i32::MIN);
+        logln!(logger, ", speed: {:.2}", -1.5f32);
+        logln!(&mut logger);
+        assert_eq!(
+            mock_writer.get_string(),
+            "steps: -2147483648, speed: -1.50\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer);
+        log!(
+            logger,
+            "{} {} {} {} {}",
+            Fixed::new(7412, 3),
+            Fixed::new(-5, 2),
+            Fixed::new(100, 0),
+            Fixed::new(i32::MIN, 9),
+            Fixed::new(-3, 1)
+        );
+        assert_eq!(
+            mock_writer.get_string(),
+            "7.412 -0.05 100 -2.147483648 -0.3"
+        );
+    }
+
+    #[test]
+    fn test_fixed_many_decimals() {
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer);
+        log!(
+            logger,
+            "{} {} {}",
+            Fixed::new(i32::MAX, 10),
+            Fixed::new(-7, 19),
+            Fixed::new(1, 255)
+        );
+        assert_eq!(
+            mock_writer.get_string(),
+            "0.2147483647 -0.0000000000000000007 0.0000000000000000001"
+        );
+    }
+
+    struct MockClock {
+        now_ms: u32,
+    }
+
+    impl LogClock for MockClock {
+        fn now_ms(&self) -> u32 {
+            self.now_ms
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let mut mock_writer = MockWriter::new();
+        let clock = MockClock { now_ms: 12_045 };
+        let mut logger = Logger::new(&mut mock_writer)
+            .tagged("idle")
+            .timestamped(&clock);
+        warn!(logger, "Battery at {} %", 15);
+        assert_eq!(
+            mock_writer.get_string(),
+            "[12.045] WARN idle: Battery at 15 %\r\n"
+        );
+
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer);
+        trace!(logger, "{}", 1);
+        assert_eq!(mock_writer.get_string(), "TRACE 1\r\n");
+    }
+
+    #[test]
+    fn test_filtered_records() {
+        let filter = LogFilter::new(Level::Info);
+        filter.set_tag_level("noisy", Level::Error);
+        let mut mock_writer = MockWriter::new();
+        let mut logger = Logger::new(&mut mock_writer).filtered(&filter);
+        debug!(logger, "hidden");
+        info!(logger, "shown");
+        let mut logger = logger.tagged("noisy");
+        warn!(logger, "hidden");
+        error!(logger, "shown");
+        // the filter can be changed while the logger uses it
+        filter.set_max_level(Level::Debug);
+        debug!(logger, "hidden");
+        filter.clear_tag_levels();
+        debug!(logger, "shown");
+        // plain logs are not filtered
+        logger.log("plain");
+        assert_eq!(
+            mock_writer.get_string(),
+            "INFO shown\r\nERROR noisy: shown\r\nDEBUG noisy: shown\r\nplain"
+        );
+    }
 }
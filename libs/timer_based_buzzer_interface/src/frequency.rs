@@ -0,0 +1,124 @@
+// Calculation of the timer registers for a given frequency.
+//
+// The timer counts at timer_clock / (prescaler + 1) and the output toggles every time it reaches
+// the auto-reload value (counter), so the frequency of the buzzer is:
+//
+//   frequency = timer_clock / ((prescaler + 1) * (counter + 1))
+//
+// Both registers are 16 bits, so there are usually several pairs of values for a frequency. The
+// search starts with the smallest prescaler that keeps the counter in range (the largest counter,
+// which gives the finest duty cycle resolution) and tries the next prescalers looking for the pair
+// with the smallest frequency error.
+
+// Number of prescalers tried after the smallest one
+const PRESCALER_CANDIDATES: u64 = 256;
+// The counter must be at least 1 so the duty cycle can be set
+const MIN_DIVISOR: u64 = 2;
+const MAX_DIVISOR: u64 = 1 << 16;
+
+/// Values of the prescaler and auto-reload (counter) registers of the timer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerRegisters {
+    pub prescaler: u16,
+    pub counter: u16,
+}
+
+/// The registers that give the frequency closest to `millihz` (in thousandths of a hertz) with a
+/// timer clocked at `timer_clock_hz`, or None if the frequency is out of the range of the timer.
+pub fn registers_for_millihz(timer_clock_hz: u32, millihz: u32) -> Option<TimerRegisters> {
+    if millihz == 0 {
+        return None;
+    }
+    let clock_millihz = timer_clock_hz as u64 * 1000;
+    let millihz = millihz as u64;
+
+    // (prescaler + 1) * (counter + 1)
+    let divisor = (clock_millihz + millihz / 2) / millihz;
+    if !(MIN_DIVISOR..=MAX_DIVISOR * MAX_DIVISOR).contains(&divisor) {
+        return None;
+    }
+
+    let min_prescaler = divisor.div_ceil(MAX_DIVISOR).max(1);
+    let max_prescaler = (min_prescaler + PRESCALER_CANDIDATES).min(MAX_DIVISOR);
+    let mut best = None;
+    let mut best_error = u64::MAX;
+    for prescaler in min_prescaler..=max_prescaler {
+        let counter = (clock_millihz + prescaler * millihz / 2) / (prescaler * millihz);
+        if !(MIN_DIVISOR..=MAX_DIVISOR).contains(&counter) {
+            continue;
+        }
+        let actual_millihz = clock_millihz / (prescaler * counter);
+        let error = actual_millihz.abs_diff(millihz);
+        if error < best_error {
+            best_error = error;
+            best = Some(TimerRegisters {
+                prescaler: (prescaler - 1) as u16,
+                counter: (counter - 1) as u16,
+            });
+            if error == 0 {
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// The frequency, in millihertz, given by the registers with a timer clocked at `timer_clock_hz`
+pub fn frequency_millihz(timer_clock_hz: u32, registers: TimerRegisters) -> u32 {
+    let divisor = (registers.prescaler as u64 + 1) * (registers.counter as u64 + 1);
+    (timer_clock_hz as u64 * 1000 / divisor) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::midi_note_millihz;
+
+    const TIMER_CLOCK_HZ: u32 = 72_000_000;
+
+    #[test]
+    fn test_known_registers() {
+        // The registers used before this calculation: 72 MHz / (71 * 2053) = 493.95 Hz
+        let registers = TimerRegisters {
+            prescaler: 70,
+            counter: 2052,
+        };
+        assert_eq!(frequency_millihz(TIMER_CLOCK_HZ, registers), 493_952);
+
+        // A4, 440 Hz: 72 MHz / 440 Hz = 163636.36, it can't be exact
+        let registers = registers_for_millihz(TIMER_CLOCK_HZ, 440_000).unwrap();
+        let millihz = frequency_millihz(TIMER_CLOCK_HZ, registers);
+        assert!(millihz.abs_diff(440_000) <= 10);
+
+        // 1 kHz is exact with the largest counter possible
+        let registers = registers_for_millihz(TIMER_CLOCK_HZ, 1_000_000).unwrap();
+        assert_eq!(frequency_millihz(TIMER_CLOCK_HZ, registers), 1_000_000);
+        assert_eq!(registers.prescaler, 1);
+        assert_eq!(registers.counter, 35_999);
+    }
+
+    #[test]
+    fn test_notes_accuracy() {
+        // Every note of the piano within 0.01 %
+        for note in 21..=108 {
+            let expected = midi_note_millihz(note).unwrap();
+            let registers = registers_for_millihz(TIMER_CLOCK_HZ, expected).unwrap();
+            let millihz = frequency_millihz(TIMER_CLOCK_HZ, registers);
+            assert!(
+                millihz.abs_diff(expected) * 10_000 <= expected,
+                "note {note}: {millihz} mHz instead of {expected} mHz"
+            );
+        }
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(registers_for_millihz(TIMER_CLOCK_HZ, 0), None);
+        // Slower than 72 MHz / 2^32
+        assert_eq!(registers_for_millihz(TIMER_CLOCK_HZ, 10), None);
+        // Faster than half the timer clock
+        assert_eq!(registers_for_millihz(1_000_000, 800_000_000), None);
+        // The lowest frequency possible
+        assert!(registers_for_millihz(TIMER_CLOCK_HZ, 17).is_some());
+    }
+}
@@ -1,6 +1,19 @@
 // Time based buzzer interface
 #![no_std]
 
+pub mod frequency;
+pub mod melody;
+pub mod pitch;
+pub mod rtttl;
+pub mod status_sounds;
+pub mod volume;
+
+#[cfg(test)]
+mod mock_buzzer;
+
+use frequency::registers_for_millihz;
+use pitch::midi_note_millihz;
+
 // this trait represents the interface of the timer based buzzer
 pub trait TimerBasedBuzzerInterface {
     // this function turns the buzzer on
@@ -9,7 +22,61 @@ pub trait TimerBasedBuzzerInterface {
     fn turn_off(&mut self);
     // this function changes the frequency of the buzzer. The prescaler and compare
     // values are used to change the frequency of the buzzer. These values depend on the
-    // timer configuration, more in concrete the speed of the clock. This speed could be
-    // given in ahother function so the registers can be calculated.
+    // timer configuration, more in concrete the speed of the clock. Use set_frequency_hz
+    // or set_frequency_millihz to have them calculated from the timer clock.
     fn change_frequency(&mut self, prescaler: u16, compare: u16);
+    // this function returns the frequency of the clock of the timer, in Hz
+    fn timer_clock_hz(&self) -> u32;
+    // this function sets the volume of the buzzer, from 0 (silent) to volume::MAX_VOLUME (100 %).
+    // The volume is kept when the frequency changes.
+    fn set_volume(&mut self, volume: u8);
+    // this function returns the volume of the buzzer
+    fn volume(&self) -> u8;
+
+    // this function changes the frequency of the buzzer to the closest one to `millihz`
+    // (thousandths of a hertz). It returns false, keeping the frequency, if the timer can't
+    // produce it.
+    fn set_frequency_millihz(&mut self, millihz: u32) -> bool {
+        match registers_for_millihz(self.timer_clock_hz(), millihz) {
+            Some(registers) => {
+                self.change_frequency(registers.prescaler, registers.counter);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // this function changes the frequency of the buzzer to the closest one to `hz`
+    fn set_frequency_hz(&mut self, hz: u32) -> bool {
+        match hz.checked_mul(1000) {
+            Some(millihz) => self.set_frequency_millihz(millihz),
+            None => false,
+        }
+    }
+
+    // this function changes the frequency of the buzzer to the MIDI note `note` (see pitch). It
+    // returns false, keeping the frequency, if the note is above pitch::MAX_NOTE.
+    fn set_note(&mut self, note: u8) -> bool {
+        match midi_note_millihz(note) {
+            Some(millihz) => self.set_frequency_millihz(millihz),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_buzzer::MockBuzzer;
+
+    #[test]
+    fn test_set_note() {
+        let mut buzzer = MockBuzzer::default();
+        assert!(buzzer.set_note(pitch::A4));
+        assert_eq!(buzzer.millihz, 440_000);
+        // the notes without a frequency keep the last one
+        assert!(!buzzer.set_note(pitch::MAX_NOTE + 1));
+        assert!(!buzzer.set_note(255));
+        assert_eq!(buzzer.millihz, 440_000);
+    }
 }
@@ -0,0 +1,331 @@
+// Non-blocking melody player.
+//
+// A melody is a static sequence of notes and rests with their durations. The player doesn't wait
+// for the notes to end: it is advanced with `tick` from the control loop (or a periodic interrupt)
+// with the current time, and it only touches the buzzer when a note starts. So a melody plays
+// while the robot keeps working, and the timing only depends on how often `tick` is called.
+//
+// The player can:
+//  - play a melody right away, interrupting the one playing and discarding the queued ones,
+//  - queue melodies to be played one after the other,
+//  - loop a melody. A looping melody plays until it is stopped or interrupted, or until there are
+//    queued melodies (then it finishes the current repetition and the queued ones are played).
+
+use crate::TimerBasedBuzzerInterface;
+
+/// A note (MIDI note number, see `pitch`) or a rest (no pitch) and its duration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub pitch: Option<u8>,
+    pub duration_ms: u32,
+}
+
+impl Note {
+    pub const fn tone(pitch: u8, duration_ms: u32) -> Self {
+        Note {
+            pitch: Some(pitch),
+            duration_ms,
+        }
+    }
+
+    pub const fn rest(duration_ms: u32) -> Self {
+        Note {
+            pitch: None,
+            duration_ms,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Melody {
+    pub notes: &'static [Note],
+    /// Repeat the melody until it is stopped
+    pub looping: bool,
+}
+
+impl Melody {
+    pub const fn new(notes: &'static [Note]) -> Self {
+        Melody {
+            notes,
+            looping: false,
+        }
+    }
+
+    pub const fn looping(notes: &'static [Note]) -> Self {
+        Melody {
+            notes,
+            looping: true,
+        }
+    }
+
+    /// Duration of one repetition of the melody
+    pub fn duration_ms(&self) -> u32 {
+        self.notes.iter().map(|note| note.duration_ms).sum()
+    }
+}
+
+/// A melody player with a queue of `N` melodies
+pub struct MelodyPlayer<const N: usize> {
+    current: Option<Melody>,
+    // Note of the current melody being played and when it ends
+    index: usize,
+    note_end_ms: u32,
+    queue: [Option<Melody>; N],
+}
+
+impl<const N: usize> Default for MelodyPlayer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MelodyPlayer<N> {
+    pub const fn new() -> Self {
+        MelodyPlayer {
+            current: None,
+            index: 0,
+            note_end_ms: 0,
+            queue: [None; N],
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Play `melody` now, interrupting the melody being played and discarding the queued ones
+    pub fn play(
+        &mut self,
+        buzzer: &mut impl TimerBasedBuzzerInterface,
+        melody: Melody,
+        now_ms: u32,
+    ) {
+        self.queue = [None; N];
+        self.start(buzzer, Some(melody), now_ms);
+    }
+
+    /// Queue `melody` to be played after the melody being played and the queued ones. If nothing is
+    /// playing, it starts on the next tick. It returns the melody back if the queue is full.
+    pub fn enqueue(&mut self, melody: Melody) -> Result<(), Melody> {
+        match self.queue.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(melody);
+                Ok(())
+            }
+            None => Err(melody),
+        }
+    }
+
+    /// Stop the melody being played and discard the queued ones
+    pub fn stop(&mut self, buzzer: &mut impl TimerBasedBuzzerInterface) {
+        self.queue = [None; N];
+        self.current = None;
+        buzzer.turn_off();
+    }
+
+    /// Advance the player to `now_ms`. It must be called periodically, more often than the shortest
+    /// note, so the notes don't get longer.
+    pub fn tick(&mut self, buzzer: &mut impl TimerBasedBuzzerInterface, now_ms: u32) {
+        let Some(mut melody) = self.current else {
+            if self.queue[0].is_some() {
+                let next = self.dequeue();
+                self.start(buzzer, next, now_ms);
+            }
+            return;
+        };
+
+        // The notes that have ended since the last tick are skipped, and the next one starts when
+        // the previous one ended (not now), so the melody doesn't drift
+        if (now_ms.wrapping_sub(self.note_end_ms) as i32) < 0 {
+            return;
+        }
+        while (now_ms.wrapping_sub(self.note_end_ms) as i32) >= 0 {
+            let note_start_ms = self.note_end_ms;
+            self.index += 1;
+            if self.index >= melody.notes.len() {
+                if !melody.looping || self.queue[0].is_some() {
+                    let next = self.dequeue();
+                    self.start(buzzer, next, note_start_ms);
+                    match self.current {
+                        Some(next) => melody = next,
+                        None => return,
+                    }
+                    continue;
+                }
+                self.index = 0;
+            }
+            self.note_end_ms = note_start_ms.wrapping_add(melody.notes[self.index].duration_ms);
+        }
+        Self::sound(buzzer, melody.notes[self.index]);
+    }
+
+    // Start playing `melody` (or stop if None) at `start_ms`. The melodies without duration are
+    // skipped, so a looping one can't get the player stuck.
+    fn start(
+        &mut self,
+        buzzer: &mut impl TimerBasedBuzzerInterface,
+        mut melody: Option<Melody>,
+        start_ms: u32,
+    ) {
+        while melody.is_some_and(|melody| melody.duration_ms() == 0) {
+            melody = self.dequeue();
+        }
+        self.current = melody;
+        match melody {
+            Some(melody) => {
+                self.index = 0;
+                self.note_end_ms = start_ms.wrapping_add(melody.notes[0].duration_ms);
+                Self::sound(buzzer, melody.notes[0]);
+            }
+            None => buzzer.turn_off(),
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<Melody> {
+        let melody = self.queue.first_mut()?.take();
+        self.queue.rotate_left(1);
+        melody
+    }
+
+    fn sound(buzzer: &mut impl TimerBasedBuzzerInterface, note: Note) {
+        match note.pitch {
+            Some(pitch) => {
+                buzzer.set_note(pitch);
+                buzzer.turn_on();
+            }
+            None => buzzer.turn_off(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_buzzer::MockBuzzer;
+    use crate::pitch::midi_note_millihz;
+
+    const C: u8 = 60;
+    const E: u8 = 64;
+    const G: u8 = 67;
+    static ARPEGGIO: [Note; 4] = [
+        Note::tone(C, 100),
+        Note::tone(E, 100),
+        Note::rest(50),
+        Note::tone(G, 200),
+    ];
+    static BEEP: [Note; 1] = [Note::tone(G, 30)];
+
+    // The note being played
+    fn note(buzzer: &MockBuzzer) -> Option<u8> {
+        let millihz = buzzer.playing()?;
+        (0..128).find(|&note| midi_note_millihz(note) == Some(millihz))
+    }
+
+    // Tick every millisecond from `from_ms` to `to_ms` (excluded) and keep the note played at
+    // every millisecond (indexed by the time)
+    fn run<const N: usize>(
+        player: &mut MelodyPlayer<N>,
+        buzzer: &mut MockBuzzer,
+        from_ms: u32,
+        to_ms: u32,
+    ) -> [Option<u8>; 2000] {
+        let mut played = [None; 2000];
+        for now_ms in from_ms..to_ms {
+            player.tick(buzzer, now_ms);
+            played[now_ms as usize] = note(buzzer);
+        }
+        played
+    }
+
+    #[test]
+    fn test_play() {
+        let mut player: MelodyPlayer<2> = MelodyPlayer::new();
+        let mut buzzer = MockBuzzer::default();
+        player.play(&mut buzzer, Melody::new(&ARPEGGIO), 1000);
+        assert_eq!(note(&buzzer), Some(C));
+
+        let played = run(&mut player, &mut buzzer, 1000, 1600);
+        assert_eq!(played[1099], Some(C));
+        assert_eq!(played[1100], Some(E));
+        assert_eq!(played[1200], None);
+        assert_eq!(played[1250], Some(G));
+        assert_eq!(played[1449], Some(G));
+        assert_eq!(played[1450], None);
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn test_late_ticks() {
+        let mut player: MelodyPlayer<2> = MelodyPlayer::new();
+        let mut buzzer = MockBuzzer::default();
+        player.play(&mut buzzer, Melody::new(&ARPEGGIO), 0);
+        // the E and the rest are skipped, and the G still ends at 450
+        player.tick(&mut buzzer, 260);
+        assert_eq!(note(&buzzer), Some(G));
+        player.tick(&mut buzzer, 449);
+        assert!(player.is_playing());
+        player.tick(&mut buzzer, 450);
+        assert!(!player.is_playing());
+        assert_eq!(buzzer.playing(), None);
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut player: MelodyPlayer<2> = MelodyPlayer::new();
+        let mut buzzer = MockBuzzer::default();
+        player.enqueue(Melody::new(&BEEP)).unwrap();
+        player.enqueue(Melody::new(&ARPEGGIO)).unwrap();
+        assert_eq!(player.enqueue(Melody::new(&BEEP)), Err(Melody::new(&BEEP)));
+
+        let played = run(&mut player, &mut buzzer, 0, 600);
+        assert_eq!(played[0], Some(G));
+        assert_eq!(played[30], Some(C));
+        assert_eq!(played[479], Some(G));
+        assert_eq!(played[480], None);
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn test_looping_and_interrupt() {
+        let mut player: MelodyPlayer<2> = MelodyPlayer::new();
+        let mut buzzer = MockBuzzer::default();
+        player.play(&mut buzzer, Melody::looping(&ARPEGGIO), 0);
+        let played = run(&mut player, &mut buzzer, 0, 1000);
+        // second and third repetitions
+        assert_eq!(played[450], Some(C));
+        assert_eq!(played[900], Some(C));
+        assert!(player.is_playing());
+
+        // a queued melody is played when the repetition ends (at 1350)
+        player.enqueue(Melody::new(&BEEP)).unwrap();
+        let played = run(&mut player, &mut buzzer, 1000, 1400);
+        assert_eq!(played[1050], Some(E));
+        assert_eq!(played[1349], Some(G));
+        assert_eq!(played[1350], Some(G));
+        assert_eq!(played[1380], None);
+
+        // interrupted by another melody
+        player.play(&mut buzzer, Melody::looping(&ARPEGGIO), 2000);
+        player.play(&mut buzzer, Melody::new(&BEEP), 2010);
+        assert_eq!(note(&buzzer), Some(G));
+        player.tick(&mut buzzer, 2040);
+        assert!(!player.is_playing());
+
+        player.play(&mut buzzer, Melody::looping(&ARPEGGIO), 3000);
+        player.stop(&mut buzzer);
+        assert_eq!(note(&buzzer), None);
+        player.tick(&mut buzzer, 3500);
+        assert_eq!(note(&buzzer), None);
+    }
+
+    #[test]
+    fn test_empty_melody() {
+        static SILENCE: [Note; 1] = [Note::rest(0)];
+        let mut player: MelodyPlayer<2> = MelodyPlayer::new();
+        let mut buzzer = MockBuzzer::default();
+        player.play(&mut buzzer, Melody::looping(&SILENCE), 0);
+        assert!(!player.is_playing());
+        player.play(&mut buzzer, Melody::looping(&[]), 0);
+        assert!(!player.is_playing());
+    }
+}
@@ -0,0 +1,46 @@
+// A buzzer for the tests: it keeps the frequency being played and counts the notes played
+
+use crate::TimerBasedBuzzerInterface;
+
+#[derive(Default)]
+pub struct MockBuzzer {
+    pub on: bool,
+    pub millihz: u32,
+    // Times the buzzer has been turned on
+    pub notes: u32,
+}
+
+impl MockBuzzer {
+    // The frequency being played, None if the buzzer is off
+    pub fn playing(&self) -> Option<u32> {
+        self.on.then_some(self.millihz)
+    }
+}
+
+impl TimerBasedBuzzerInterface for MockBuzzer {
+    fn turn_on(&mut self) {
+        self.on = true;
+        self.notes += 1;
+    }
+
+    fn turn_off(&mut self) {
+        self.on = false;
+    }
+
+    fn change_frequency(&mut self, _prescaler: u16, _compare: u16) {}
+
+    fn timer_clock_hz(&self) -> u32 {
+        72_000_000
+    }
+
+    fn set_volume(&mut self, _volume: u8) {}
+
+    fn volume(&self) -> u8 {
+        100
+    }
+
+    fn set_frequency_millihz(&mut self, millihz: u32) -> bool {
+        self.millihz = millihz;
+        true
+    }
+}
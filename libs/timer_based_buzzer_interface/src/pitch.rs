@@ -0,0 +1,97 @@
+// Musical pitches as MIDI note numbers.
+//
+// A MIDI note number identifies a note of the equal tempered scale: 60 is the middle C (C4) and 69
+// is the A4 (440 Hz). Every octave has 12 notes, so note + 12 is the same note one octave higher,
+// with twice the frequency. The frequencies of the highest octave (notes 120 to 131) are stored in
+// millihertz, and the lower octaves are derived halving them, so the table is small and precise.
+
+/// The names of the notes of an octave
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteName {
+    C,
+    CSharp,
+    D,
+    DSharp,
+    E,
+    F,
+    FSharp,
+    G,
+    GSharp,
+    A,
+    ASharp,
+    B,
+}
+
+/// The MIDI note number of the note `name` in `octave` (C4 is the middle C). The octaves go from
+/// -1 to 9, it panics with other octaves (it doesn't compile if it is evaluated in a constant).
+pub const fn midi_note(name: NoteName, octave: i8) -> u8 {
+    assert!(octave >= -1 && octave <= 9, "the octaves go from -1 to 9");
+    ((octave + 1) as u8) * 12 + name as u8
+}
+
+pub const C4: u8 = midi_note(NoteName::C, 4);
+pub const A4: u8 = midi_note(NoteName::A, 4);
+
+// Frequencies of the notes 120 (C9) to 131 (B9) in millihertz
+const TOP_OCTAVE_MILLIHZ: [u32; 12] = [
+    8_372_018, 8_869_844, 9_397_273, 9_956_063, 10_548_082, 11_175_303, 11_839_822, 12_543_854,
+    13_289_750, 14_080_000, 14_917_242, 15_804_266,
+];
+const TOP_OCTAVE: u8 = 10;
+
+/// The highest note with a frequency, B9. The notes above 127 are not valid MIDI notes, but they
+/// are accepted up to this one.
+pub const MAX_NOTE: u8 = TOP_OCTAVE * 12 + 11;
+
+/// The frequency of the MIDI note `note` in millihertz, or None if it is above `MAX_NOTE`
+pub const fn midi_note_millihz(note: u8) -> Option<u32> {
+    if note > MAX_NOTE {
+        return None;
+    }
+    let millihz = TOP_OCTAVE_MILLIHZ[(note % 12) as usize];
+    let shift = TOP_OCTAVE - note / 12;
+    if shift == 0 {
+        return Some(millihz);
+    }
+    // Rounded to the closest millihertz
+    Some((millihz + (1 << (shift - 1))) >> shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_numbers() {
+        assert_eq!(C4, 60);
+        assert_eq!(A4, 69);
+        assert_eq!(midi_note(NoteName::C, -1), 0);
+        assert_eq!(midi_note(NoteName::A, 0), 21);
+        assert_eq!(midi_note(NoteName::G, 9), 127);
+        assert_eq!(midi_note(NoteName::B, 9), MAX_NOTE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_octave() {
+        midi_note(NoteName::C, 21);
+    }
+
+    #[test]
+    fn test_frequencies() {
+        assert_eq!(midi_note_millihz(A4), Some(440_000));
+        assert_eq!(midi_note_millihz(A4 + 12), Some(880_000));
+        assert_eq!(midi_note_millihz(C4), Some(261_626));
+        assert_eq!(midi_note_millihz(21), Some(27_500));
+        assert_eq!(midi_note_millihz(0), Some(8_176));
+        assert_eq!(midi_note_millihz(127), Some(12_543_854));
+        assert_eq!(midi_note_millihz(MAX_NOTE), Some(15_804_266));
+    }
+
+    #[test]
+    fn test_notes_out_of_range() {
+        assert_eq!(midi_note_millihz(MAX_NOTE + 1), None);
+        assert_eq!(midi_note_millihz(200), None);
+        assert_eq!(midi_note_millihz(255), None);
+    }
+}
@@ -0,0 +1,349 @@
+// RTTTL (Ring Tone Text Transfer Language) parser.
+//
+// A RTTTL ringtone has three sections separated by colons: the name, the defaults and the notes.
+//
+//   Simpsons:d=4,o=5,b=160:c.6,e6,f#6,8a6,g.6,e6,c6,8a,8f#,8f#,8f#,2g
+//
+// The defaults are the duration (d, as a fraction of a whole note: 1, 2, 4, 8, 16 or 32), the
+// octave (o) and the tempo (b, quarter notes per minute). When missing, they are d=4, o=6 and
+// b=63. Every note is written as [duration]letter[#][.][octave][.], where the letter is c, d, e,
+// f, g, a, b or p (pause), # raises the note a semitone and the dot makes it 1.5 times longer.
+//
+// The parser is a const fn, so the ringtones can be parsed at compile time into statics (an
+// invalid ringtone is a compile error):
+//
+//   static SIMPSONS: Ringtone<32> = rtttl::ringtone("Simpsons:d=4,o=5,b=160:c.6,e6,f#6,8a6");
+//   player.play(&mut buzzer, SIMPSONS.melody(), now_ms);
+
+use crate::melody::{Melody, Note};
+
+const DEFAULT_DURATION: u32 = 4;
+const DEFAULT_OCTAVE: u32 = 6;
+const DEFAULT_BPM: u32 = 63;
+const MAX_OCTAVE: u32 = 9;
+const MAX_BPM: u32 = 900;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtttlErrorKind {
+    /// The name, defaults or notes section is missing
+    MissingSection,
+    /// A default value is not d, o or b, or it has no value
+    InvalidDefault,
+    InvalidDuration,
+    InvalidNote,
+    InvalidOctave,
+    InvalidBpm,
+    /// The ringtone has more notes than the capacity of the ringtone
+    TooManyNotes,
+}
+
+/// An error parsing a ringtone, and its position (byte offset) in the ringtone text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtttlError {
+    pub kind: RtttlErrorKind,
+    pub position: usize,
+}
+
+/// The notes of a ringtone, up to `N`
+#[derive(Clone, Copy, Debug)]
+pub struct Ringtone<const N: usize> {
+    notes: [Note; N],
+    len: usize,
+}
+
+impl<const N: usize> Ringtone<N> {
+    pub const fn notes(&self) -> &[Note] {
+        self.notes.split_at(self.len).0
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The ringtone as a melody for the melody player
+    pub const fn melody(&'static self) -> Melody {
+        Melody::new(self.notes())
+    }
+}
+
+/// Parse a ringtone, panicking if it is not valid. In a const or static, the panic is a compile
+/// error.
+pub const fn ringtone<const N: usize>(rtttl: &str) -> Ringtone<N> {
+    match parse(rtttl) {
+        Ok(ringtone) => ringtone,
+        Err(_) => panic!("invalid RTTTL ringtone"),
+    }
+}
+
+/// Parse a ringtone
+pub const fn parse<const N: usize>(rtttl: &str) -> Result<Ringtone<N>, RtttlError> {
+    let bytes = rtttl.as_bytes();
+
+    // The name is skipped
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != b':' {
+        i += 1;
+    }
+    if i == bytes.len() {
+        return Err(error(RtttlErrorKind::MissingSection, i));
+    }
+    i += 1;
+
+    // Defaults section
+    let mut duration = DEFAULT_DURATION;
+    let mut octave = DEFAULT_OCTAVE;
+    let mut bpm = DEFAULT_BPM;
+    loop {
+        i = skip_spaces(bytes, i);
+        if i == bytes.len() {
+            return Err(error(RtttlErrorKind::MissingSection, i));
+        }
+        if bytes[i] == b':' {
+            i += 1;
+            break;
+        }
+
+        let key_position = i;
+        let key = bytes[i].to_ascii_lowercase();
+        i = skip_spaces(bytes, i + 1);
+        if i == bytes.len() || bytes[i] != b'=' {
+            return Err(error(RtttlErrorKind::InvalidDefault, i));
+        }
+        i = skip_spaces(bytes, i + 1);
+        let value_position = i;
+        let value = match parse_number(bytes, i) {
+            Some((value, next)) => {
+                i = next;
+                value
+            }
+            None => return Err(error(RtttlErrorKind::InvalidDefault, i)),
+        };
+        match key {
+            b'd' if is_valid_duration(value) => duration = value,
+            b'd' => return Err(error(RtttlErrorKind::InvalidDuration, value_position)),
+            b'o' if value <= MAX_OCTAVE => octave = value,
+            b'o' => return Err(error(RtttlErrorKind::InvalidOctave, value_position)),
+            b'b' if value > 0 && value <= MAX_BPM => bpm = value,
+            b'b' => return Err(error(RtttlErrorKind::InvalidBpm, value_position)),
+            _ => return Err(error(RtttlErrorKind::InvalidDefault, key_position)),
+        }
+
+        i = skip_spaces(bytes, i);
+        if i < bytes.len() && bytes[i] == b',' {
+            i += 1;
+        }
+    }
+
+    // Notes section
+    let whole_note_ms = 4 * 60_000 / bpm;
+    let mut ringtone = Ringtone {
+        notes: [Note::rest(0); N],
+        len: 0,
+    };
+    loop {
+        i = skip_spaces(bytes, i);
+        if i == bytes.len() {
+            break;
+        }
+        let note_position = i;
+
+        let note_duration = match parse_number(bytes, i) {
+            Some((value, next)) => {
+                if !is_valid_duration(value) {
+                    return Err(error(RtttlErrorKind::InvalidDuration, i));
+                }
+                i = next;
+                value
+            }
+            None => duration,
+        };
+
+        if i == bytes.len() {
+            return Err(error(RtttlErrorKind::InvalidNote, i));
+        }
+        let mut semitone = match bytes[i].to_ascii_lowercase() {
+            b'c' => 0,
+            b'd' => 2,
+            b'e' => 4,
+            b'f' => 5,
+            b'g' => 7,
+            b'a' => 9,
+            b'b' => 11,
+            b'p' => -1,
+            _ => return Err(error(RtttlErrorKind::InvalidNote, i)),
+        };
+        i += 1;
+        if i < bytes.len() && bytes[i] == b'#' && semitone >= 0 {
+            semitone += 1;
+            i += 1;
+        }
+
+        // The dot can be before or after the octave
+        let mut dotted = false;
+        if i < bytes.len() && bytes[i] == b'.' {
+            dotted = true;
+            i += 1;
+        }
+        let note_octave = match parse_number(bytes, i) {
+            Some((value, next)) => {
+                if value > MAX_OCTAVE {
+                    return Err(error(RtttlErrorKind::InvalidOctave, i));
+                }
+                i = next;
+                value
+            }
+            None => octave,
+        };
+        if i < bytes.len() && bytes[i] == b'.' {
+            dotted = true;
+            i += 1;
+        }
+
+        i = skip_spaces(bytes, i);
+        if i < bytes.len() {
+            if bytes[i] != b',' {
+                return Err(error(RtttlErrorKind::InvalidNote, i));
+            }
+            i += 1;
+        }
+
+        if ringtone.len == N {
+            return Err(error(RtttlErrorKind::TooManyNotes, note_position));
+        }
+        let mut duration_ms = whole_note_ms / note_duration;
+        if dotted {
+            duration_ms += duration_ms / 2;
+        }
+        ringtone.notes[ringtone.len] = if semitone < 0 {
+            Note::rest(duration_ms)
+        } else {
+            let pitch = (note_octave + 1) * 12 + semitone as u32;
+            if pitch > 127 {
+                return Err(error(RtttlErrorKind::InvalidOctave, note_position));
+            }
+            Note::tone(pitch as u8, duration_ms)
+        };
+        ringtone.len += 1;
+    }
+    Ok(ringtone)
+}
+
+const fn error(kind: RtttlErrorKind, position: usize) -> RtttlError {
+    RtttlError { kind, position }
+}
+
+const fn is_valid_duration(duration: u32) -> bool {
+    matches!(duration, 1 | 2 | 4 | 8 | 16 | 32)
+}
+
+const fn skip_spaces(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+// Parse the decimal number at `i`, returning it and the position after it, or None if there is no
+// number (or it doesn't fit in a u32)
+const fn parse_number(bytes: &[u8], mut i: usize) -> Option<(u32, usize)> {
+    let start = i;
+    let mut value: u32 = 0;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        value = match value.checked_mul(10) {
+            Some(value) => match value.checked_add((bytes[i] - b'0') as u32) {
+                Some(value) => value,
+                None => return None,
+            },
+            None => return None,
+        };
+        i += 1;
+    }
+    if i == start {
+        return None;
+    }
+    Some((value, i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{midi_note, NoteName};
+
+    static SIMPSONS: Ringtone<32> = ringtone(
+        "The Simpsons:d=4,o=5,b=160:c.6,e6,f#6,8a6,g.6,e6,c6,8a,8f#,8f#,8f#,2g,8p,8p,8f#,8f#,8f#,\
+         8g,a#.,8c6,8c6,8c6,c6",
+    );
+
+    #[test]
+    fn test_known_ringtone() {
+        assert_eq!(SIMPSONS.len(), 23);
+        let notes = SIMPSONS.notes();
+        // 160 bpm: a quarter note lasts 375 ms
+        assert_eq!(notes[0], Note::tone(midi_note(NoteName::C, 6), 562));
+        assert_eq!(notes[2], Note::tone(midi_note(NoteName::FSharp, 6), 375));
+        assert_eq!(notes[3], Note::tone(midi_note(NoteName::A, 6), 187));
+        assert_eq!(notes[7], Note::tone(midi_note(NoteName::A, 5), 187));
+        assert_eq!(notes[11], Note::tone(midi_note(NoteName::G, 5), 750));
+        assert_eq!(notes[12], Note::rest(187));
+        assert_eq!(notes[18], Note::tone(midi_note(NoteName::ASharp, 5), 562));
+        assert_eq!(SIMPSONS.melody().notes.len(), 23);
+    }
+
+    #[test]
+    fn test_defaults() {
+        // d=4, o=6, b=63 when missing, in any order, with spaces and upper case letters
+        let ringtone: Ringtone<4> = parse("defaults::C, 8e.5, 2p").unwrap();
+        assert_eq!(
+            ringtone.notes()[0],
+            Note::tone(midi_note(NoteName::C, 6), 952)
+        );
+        assert_eq!(
+            ringtone.notes()[1],
+            Note::tone(midi_note(NoteName::E, 5), 714)
+        );
+        assert_eq!(ringtone.notes()[2], Note::rest(1904));
+
+        let ringtone: Ringtone<4> = parse("order: b = 120 , o=4:d,32g#7").unwrap();
+        assert_eq!(
+            ringtone.notes()[0],
+            Note::tone(midi_note(NoteName::D, 4), 500)
+        );
+        assert_eq!(
+            ringtone.notes()[1],
+            Note::tone(midi_note(NoteName::GSharp, 7), 62)
+        );
+
+        // the dot after the octave, and an empty ringtone
+        let ringtone: Ringtone<4> = parse("dot:d=8,o=5,b=100:a4.,").unwrap();
+        assert_eq!(
+            ringtone.notes(),
+            [Note::tone(midi_note(NoteName::A, 4), 450)]
+        );
+        let ringtone: Ringtone<4> = parse("empty:d=4:").unwrap();
+        assert!(ringtone.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        fn kind(rtttl: &str) -> (RtttlErrorKind, usize) {
+            let error = parse::<4>(rtttl).unwrap_err();
+            (error.kind, error.position)
+        }
+        assert_eq!(kind("no sections"), (RtttlErrorKind::MissingSection, 11));
+        assert_eq!(kind("x:d=4,o=5"), (RtttlErrorKind::MissingSection, 9));
+        assert_eq!(kind("x:q=4:c"), (RtttlErrorKind::InvalidDefault, 2));
+        assert_eq!(kind("x:d=:c"), (RtttlErrorKind::InvalidDefault, 4));
+        assert_eq!(kind("x:d=3:c"), (RtttlErrorKind::InvalidDuration, 4));
+        assert_eq!(kind("x:b=0:c"), (RtttlErrorKind::InvalidBpm, 4));
+        assert_eq!(kind("x:o=12:c"), (RtttlErrorKind::InvalidOctave, 4));
+        assert_eq!(kind("x::c,h"), (RtttlErrorKind::InvalidNote, 5));
+        assert_eq!(kind("x::c,6,d"), (RtttlErrorKind::InvalidDuration, 5));
+        assert_eq!(kind("x::c d"), (RtttlErrorKind::InvalidNote, 5));
+        assert_eq!(kind("x::a9"), (RtttlErrorKind::InvalidOctave, 3));
+        assert_eq!(kind("x::c,d,e,f,g"), (RtttlErrorKind::TooManyNotes, 11));
+    }
+}
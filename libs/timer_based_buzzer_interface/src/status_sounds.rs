@@ -0,0 +1,179 @@
+// Status sounds.
+//
+// A small vocabulary of sounds, so the same sound means the same thing everywhere and the user can
+// follow the robot without a serial connection:
+//  - cues: short melodies for the usual events (boot, calibration done, line lost...). They
+//    are melodies, so they can be played with the melody player without blocking, or blocking with
+//    `play_blocking`.
+//  - fault codes: a long low beep followed by two groups of short high beeps, the group of the
+//    fault and the detail (for example, the group of the light sensor array and the number of the
+//    dead sensor). A fault code 1-3 is played as: long beep, 1 beep, pause, 3 beeps.
+
+use crate::melody::{Melody, Note};
+use crate::pitch::{midi_note, NoteName};
+use crate::TimerBasedBuzzerInterface;
+
+/// The status cues
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cue {
+    /// The robot has started and is ready
+    BootOk,
+    /// The hardware check has found no faults
+    HardwareCheckOk,
+    CalibrationDone,
+    /// The robot is about to start moving
+    StartCountdown,
+    LineLost,
+    /// The battery is getting low
+    BatteryWarning,
+}
+
+const C5: u8 = midi_note(NoteName::C, 5);
+const D5: u8 = midi_note(NoteName::D, 5);
+const E5: u8 = midi_note(NoteName::E, 5);
+const G5: u8 = midi_note(NoteName::G, 5);
+const A5: u8 = midi_note(NoteName::A, 5);
+const G4: u8 = midi_note(NoteName::G, 4);
+const F4: u8 = midi_note(NoteName::F, 4);
+
+static BOOT_OK: [Note; 3] = [Note::tone(C5, 80), Note::tone(E5, 80), Note::tone(G5, 160)];
+static HARDWARE_CHECK_OK: [Note; 2] = [Note::tone(C5, 100), Note::tone(G5, 200)];
+static CALIBRATION_DONE: [Note; 3] = [Note::tone(D5, 100), Note::rest(50), Note::tone(D5, 100)];
+static START_COUNTDOWN: [Note; 6] = [
+    Note::tone(A5, 100),
+    Note::rest(100),
+    Note::tone(A5, 100),
+    Note::rest(100),
+    Note::tone(A5, 100),
+    Note::rest(100),
+];
+static LINE_LOST: [Note; 3] = [
+    Note::tone(G5, 150),
+    Note::tone(D5, 150),
+    Note::tone(G4, 300),
+];
+static BATTERY_WARNING: [Note; 3] = [Note::tone(F4, 200), Note::rest(100), Note::tone(F4, 200)];
+
+impl Cue {
+    pub fn melody(self) -> Melody {
+        Melody::new(match self {
+            Cue::BootOk => &BOOT_OK,
+            Cue::HardwareCheckOk => &HARDWARE_CHECK_OK,
+            Cue::CalibrationDone => &CALIBRATION_DONE,
+            Cue::StartCountdown => &START_COUNTDOWN,
+            Cue::LineLost => &LINE_LOST,
+            Cue::BatteryWarning => &BATTERY_WARNING,
+        })
+    }
+
+    pub fn notes(self) -> impl Iterator<Item = Note> {
+        self.melody().notes.iter().copied()
+    }
+}
+
+/// The highest number of beeps of a group of a fault code
+pub const MAX_FAULT_BEEPS: u8 = 9;
+
+const FAULT_TONE: u8 = A5;
+const FAULT_START_TONE: u8 = C5;
+const FAULT_START_MS: u32 = 800;
+const FAULT_PAUSE_MS: u32 = 600;
+const BEEP_MS: u32 = 100;
+const BEEP_GAP_MS: u32 = 200;
+
+/// A fault code: a group (what failed) and a detail, both from 1 to MAX_FAULT_BEEPS
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultCode {
+    pub group: u8,
+    pub detail: u8,
+}
+
+impl FaultCode {
+    /// Panics if the group or the detail are not between 1 and MAX_FAULT_BEEPS (at compile time
+    /// for the constants)
+    pub const fn new(group: u8, detail: u8) -> Self {
+        assert!(
+            group >= 1 && group <= MAX_FAULT_BEEPS,
+            "invalid fault group"
+        );
+        assert!(
+            detail >= 1 && detail <= MAX_FAULT_BEEPS,
+            "invalid fault detail"
+        );
+        FaultCode { group, detail }
+    }
+
+    pub fn notes(self) -> impl Iterator<Item = Note> {
+        [
+            Note::tone(FAULT_START_TONE, FAULT_START_MS),
+            Note::rest(FAULT_PAUSE_MS),
+        ]
+        .into_iter()
+        .chain(beeps(FAULT_TONE, self.group))
+        .chain([Note::rest(FAULT_PAUSE_MS)])
+        .chain(beeps(FAULT_TONE, self.detail))
+    }
+}
+
+/// `count` short beeps of `pitch`, each one followed by a short rest (used to play numbers)
+pub fn beeps(pitch: u8, count: u8) -> impl Iterator<Item = Note> {
+    (0..count).flat_map(move |_| [Note::tone(pitch, BEEP_MS), Note::rest(BEEP_GAP_MS)])
+}
+
+/// Play `notes` waiting for every note to end with `wait_ms`, and turn the buzzer off at the end
+pub fn play_blocking(
+    buzzer: &mut impl TimerBasedBuzzerInterface,
+    notes: impl IntoIterator<Item = Note>,
+    mut wait_ms: impl FnMut(u32),
+) {
+    for note in notes {
+        match note.pitch {
+            Some(pitch) => {
+                buzzer.set_note(pitch);
+                buzzer.turn_on();
+            }
+            None => buzzer.turn_off(),
+        }
+        wait_ms(note.duration_ms);
+    }
+    buzzer.turn_off();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_buzzer::MockBuzzer;
+
+    #[test]
+    fn test_fault_code() {
+        let mut notes = FaultCode::new(1, 3).notes();
+        assert_eq!(
+            notes.next(),
+            Some(Note::tone(FAULT_START_TONE, FAULT_START_MS))
+        );
+        assert_eq!(notes.next(), Some(Note::rest(FAULT_PAUSE_MS)));
+        assert_eq!(notes.next(), Some(Note::tone(FAULT_TONE, BEEP_MS)));
+        assert_eq!(notes.next(), Some(Note::rest(BEEP_GAP_MS)));
+        assert_eq!(notes.next(), Some(Note::rest(FAULT_PAUSE_MS)));
+        let detail = notes.filter(|note| note.pitch.is_some()).count();
+        assert_eq!(detail, 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_fault_code() {
+        FaultCode::new(1, MAX_FAULT_BEEPS + 1);
+    }
+
+    #[test]
+    fn test_play_blocking() {
+        let mut buzzer = MockBuzzer::default();
+        let mut elapsed_ms = 0;
+        play_blocking(&mut buzzer, Cue::CalibrationDone.notes(), |ms| {
+            elapsed_ms += ms
+        });
+        assert_eq!(buzzer.notes, 2);
+        assert!(!buzzer.on);
+        assert_eq!(elapsed_ms, Cue::CalibrationDone.melody().duration_ms());
+    }
+}
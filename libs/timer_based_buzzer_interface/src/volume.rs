@@ -0,0 +1,32 @@
+// Volume of the buzzer.
+//
+// The buzzer is driven with a PWM signal, and it is loudest with a 50 % duty cycle (a square
+// wave). A shorter pulse gives less energy to the buzzer, so the volume is set with the duty cycle:
+// from 0 % (silent) to 50 % (full volume), keeping the frequency.
+
+/// Full volume, in percent
+pub const MAX_VOLUME: u8 = 100;
+
+/// The compare value of the PWM channel for `volume` (0 to 100 %) with the auto-reload value
+/// `counter` (the period of the timer is counter + 1)
+pub fn compare_for_volume(counter: u16, volume: u8) -> u16 {
+    let period = counter as u32 + 1;
+    let volume = volume.min(MAX_VOLUME) as u32;
+    (period * volume / (2 * MAX_VOLUME as u32)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_for_volume() {
+        // full volume is a 50 % duty cycle
+        assert_eq!(compare_for_volume(2052, MAX_VOLUME), 1026);
+        assert_eq!(compare_for_volume(u16::MAX, MAX_VOLUME), 32768);
+        assert_eq!(compare_for_volume(35_999, 50), 9000);
+        assert_eq!(compare_for_volume(35_999, 0), 0);
+        // the volume is limited to the maximum
+        assert_eq!(compare_for_volume(35_999, 200), 18000);
+    }
+}
@@ -1,7 +1,8 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+  /* The last 1K page of the flash is reserved for the settings (mightybuga_bsc settings_storage) */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 63K
   RAM : ORIGIN = 0x20000000, LENGTH = 20K
 }
 
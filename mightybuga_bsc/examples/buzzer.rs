@@ -22,7 +22,7 @@ fn main() -> ! {
     for _ in 0..3 {
         delay.delay(2000.millis());
         led_d1.set_high();
-        buzzer.change_frequency(70, 3000);
+        buzzer.set_frequency_hz(338);
         buzzer.turn_on();
         delay.delay_ms(2000_u16);
         led_d1.set_low();
@@ -1,46 +1,94 @@
 use crate::{
+    clock::Clock,
     hal::{
         gpio::{Analog, Pin},
         prelude::_embedded_hal_adc_OneShot,
     },
     ADC_POOL,
 };
-use battery_sensor_controller::BatterySensorController;
+use battery_sensor_controller::{
+    conversion::AdcConversion,
+    level_detector::BatteryLevelDetector,
+    state_of_charge::{BatteryLevel, StateOfChargeEstimator},
+    BatterySensorController,
+};
 use heapless::pool::arc::Arc;
 
 /// The battery sensor used to detect the battery level.
-/// It uses 1 analog pin connected to the resistor divider and the ADC1 to read the voltage.
+/// It uses 1 analog pin connected to the resistor divider and the ADC1 to read the voltage. The
+/// internal reference of the ADC (VREFINT) is read too, to compensate the variations of the supply.
 pub struct BatterySensor {
     /// The pin for the battery sensor
     pub sensor_0: Pin<'B', 0, Analog>,
 
     pub adc: Arc<ADC_POOL>,
+
+    /// Converts the raw readings to millivolts, with the calibration factor of this robot
+    pub conversion: AdcConversion,
+
+    /// Estimates the state of charge from the battery voltage (2S LiPo by default)
+    pub state_of_charge: StateOfChargeEstimator,
+    /// Debounces the battery level, so the voltage sags when the motors are running don't change it
+    pub level_detector: BatteryLevelDetector,
+    /// Used to time how long a new battery level has been seen
+    pub clock: Clock,
+}
+
+impl BatterySensor {
+    /// Adjust the calibration factor so the current battery voltage reads `known_millivolts`
+    /// (measured with a multimeter). It returns the new calibration factor, to be stored in the
+    /// settings, or None (and the factor is not changed) if `known_millivolts` is not a voltage of
+    /// the battery or the factor would be out of the valid band.
+    pub fn calibrate(&mut self, known_millivolts: u16) -> Option<u32> {
+        if !self.state_of_charge.is_within_curve(known_millivolts) {
+            return None;
+        }
+        let measured_millivolts = self.get_battery_millivolts();
+        match self
+            .conversion
+            .calibrate(measured_millivolts, known_millivolts)
+        {
+            true => Some(self.conversion.calibration_factor),
+            false => None,
+        }
+    }
 }
 
 impl BatterySensorController for BatterySensor {
     fn get_battery_millivolts(&mut self) -> u16 {
         let mut adc = self.adc.borrow_mut();
 
-        // Read the battery voltage, take 10 samples and average them (later, so we don't lose precision):
+        // Read the battery voltage and VREFINT, take 10 samples of each and add them up (the
+        // conversion only needs their ratio, so we don't lose precision averaging them):
         let mut battery_voltage_by_10: u32 = 0;
+        let mut vrefint_by_10: u32 = 0;
         for _ in 0..10 {
             let sample: u16 = adc.read(&mut self.sensor_0).unwrap();
             battery_voltage_by_10 += sample as u32;
+            vrefint_by_10 += adc.read_vref() as u32;
         }
 
-        // Convert the voltage to millivolts. We multiply the raw value by the battery voltage and
-        // divide by the resister divider. The maximum value is 8.4V and the resister divider is
-        // 47k and 20k. The raw value is 12 bits, so the maximum value is 4096 that corresponds to
-        // 2.5 volts in the ADC. The formula is:
-        let raw_to_millivolts_multiplier_by_1000 = 2857;
-        let battery_voltage_mv =
-            (battery_voltage_by_10 * raw_to_millivolts_multiplier_by_1000 / 10000) as u16;
+        self.conversion
+            .battery_millivolts(battery_voltage_by_10, vrefint_by_10)
+    }
+
+    fn get_state_of_charge(&mut self) -> u8 {
+        let battery_voltage_mv = self.get_battery_millivolts();
+        self.state_of_charge.percent(battery_voltage_mv)
+    }
 
-        battery_voltage_mv
+    fn get_battery_level(&mut self) -> BatteryLevel {
+        let percent = self.get_state_of_charge();
+        self.level_detector
+            .update(&self.state_of_charge, percent, self.clock.now_ms());
+        self.level_detector.level()
     }
 
-    fn is_battery_low(&mut self) -> bool {
+    fn get_battery_level_under_load(&mut self) -> BatteryLevel {
         let battery_voltage_mv = self.get_battery_millivolts();
-        battery_voltage_mv < 4900
+        let percent = self.state_of_charge.percent_under_load(battery_voltage_mv);
+        self.level_detector
+            .update(&self.state_of_charge, percent, self.clock.now_ms());
+        self.level_detector.level()
     }
 }
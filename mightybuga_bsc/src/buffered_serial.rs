@@ -0,0 +1,168 @@
+// Interrupt driven, buffered serial port transmission.
+//
+// Writing to the serial port byte by byte waits for every byte to be sent, so a log line stalls the
+// control loop for milliseconds at 115200 bauds. Here the bytes written are stored in a ring buffer
+// and the USART1 interrupt sends them while the transmit register is empty, so writing never
+// waits. The reception is not buffered, it is the one of the HAL.
+//
+// If the buffer is full, the new bytes (or the oldest ones, see `OverflowPolicy`) are dropped and
+// counted, instead of waiting for room.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use heapless::Deque;
+use stm32f1xx_hal::pac::{self, interrupt, Interrupt, USART1};
+use stm32f1xx_hal::serial::{Rx, Serial, Tx};
+
+// Bytes waiting to be sent, enough for a menu of the applications
+pub const TX_BUFFER_LEN: usize = 1024;
+
+// Bytes buffered in every critical section of a write, so a long write doesn't delay the other
+// interrupts (e.g. the buttons) for long
+const WRITE_CHUNK_LEN: usize = 16;
+
+static TX_BUFFER: Mutex<RefCell<Deque<u8, TX_BUFFER_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+
+// What to drop when the buffer is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // The bytes being written, so the text already buffered is sent complete
+    DropNewest,
+    // The oldest bytes of the buffer, so the latest text is sent
+    DropOldest,
+}
+
+// The serial port of the board: buffered transmission and HAL reception
+pub struct BufferedSerial {
+    pub tx: BufferedTx,
+    pub rx: Rx<USART1>,
+}
+
+impl BufferedSerial {
+    pub(crate) fn new<PINS>(serial: Serial<USART1, PINS>) -> Self {
+        let (tx, rx) = serial.split();
+        // SAFETY: the handler only uses the buffer and the transmission registers of USART1
+        unsafe { NVIC::unmask(Interrupt::USART1) };
+        BufferedSerial {
+            tx: BufferedTx {
+                _tx: tx,
+                overflow_policy: OverflowPolicy::DropNewest,
+                dropped: 0,
+            },
+            rx,
+        }
+    }
+}
+
+pub struct BufferedTx {
+    // The transmission of the HAL is kept so nothing else can use it
+    _tx: Tx<USART1>,
+    overflow_policy: OverflowPolicy,
+    dropped: u32,
+}
+
+impl BufferedTx {
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    // Number of bytes dropped because the buffer was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    // Number of bytes waiting to be sent
+    pub fn pending(&self) -> usize {
+        cortex_m::interrupt::free(|cs| TX_BUFFER.borrow(cs).borrow().len())
+    }
+
+    // Buffer `bytes` to be sent. It never waits: if the buffer gets full, the bytes are dropped as
+    // the overflow policy says.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(WRITE_CHUNK_LEN) {
+            cortex_m::interrupt::free(|cs| {
+                let mut buffer = TX_BUFFER.borrow(cs).borrow_mut();
+                for &byte in chunk {
+                    if buffer.is_full() {
+                        self.dropped = self.dropped.wrapping_add(1);
+                        match self.overflow_policy {
+                            OverflowPolicy::DropNewest => continue,
+                            OverflowPolicy::DropOldest => {
+                                buffer.pop_front();
+                            }
+                        }
+                    }
+                    let _ = buffer.push_back(byte);
+                }
+                if !buffer.is_empty() {
+                    // SAFETY: the interrupt can't modify the register inside the critical section
+                    let usart = unsafe { &*pac::USART1::ptr() };
+                    usart.cr1.modify(|_, w| w.txeie().set_bit());
+                }
+            });
+        }
+    }
+
+    // Buffer a byte to be sent, it never returns WouldBlock
+    pub fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        self.write_bytes(&[byte]);
+        Ok(())
+    }
+
+    // Returns WouldBlock until all the buffered bytes have been sent
+    pub fn flush(&mut self) -> nb::Result<(), Infallible> {
+        // SAFETY: reading the status register has no side effects
+        let usart = unsafe { &*pac::USART1::ptr() };
+        if self.pending() > 0 || usart.sr.read().tc().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal::serial::Write<u8> for BufferedTx {
+    type Error = Infallible;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        BufferedTx::write(self, byte)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        BufferedTx::flush(self)
+    }
+}
+
+// The blocking writes only wait in bflush, the writes are buffered (a chunk at a time, not byte by
+// byte as the default blocking implementation would do)
+impl embedded_hal::blocking::serial::Write<u8> for BufferedTx {
+    type Error = Infallible;
+
+    fn bwrite_all(&mut self, buffer: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(buffer);
+        Ok(())
+    }
+
+    fn bflush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(self.flush())
+    }
+}
+
+#[interrupt]
+fn USART1() {
+    // SAFETY: the transmission registers are only modified here and inside critical sections
+    let usart = unsafe { &*pac::USART1::ptr() };
+    if usart.sr.read().txe().bit_is_clear() {
+        return;
+    }
+    match cortex_m::interrupt::free(|cs| TX_BUFFER.borrow(cs).borrow_mut().pop_front()) {
+        Some(byte) => usart.dr.write(|w| w.dr().bits(byte as u16)),
+        // Nothing else to send, the interrupt is enabled again by the next write
+        None => usart.cr1.modify(|_, w| w.txeie().clear_bit()),
+    }
+}
@@ -0,0 +1,181 @@
+// Interrupt driven button events.
+//
+// The buttons (PB13, PC15 and PC14) trigger the EXTI15_10 interrupt on both edges. The interrupt
+// handler timestamps every edge with the monotonic clock and pushes it into a lock-free queue, so
+// no press is lost while the main loop is busy (e.g. blocked in a delay). The main loop takes the
+// edges from the queue through the `ButtonEvents` handle of the board, one by one or grouped in
+// chords (the buttons pressed together, e.g. 1+3).
+//
+// The bounces are filtered in the handler: an edge is ignored if it doesn't change the state of
+// the button or if it comes less than EDGE_DEBOUNCE_MS after the previous accepted edge of the same
+// button. If the queue is full, the new edges are dropped (and counted), so the main loop must take
+// them often enough or clear the queue.
+//
+// The last edge of a bounce can come within the debounce time and there are no more edges to report
+// the final state of the button. So, when the main loop takes the edges, the buttons are read again
+// and an edge is queued for every button whose state differs from the last accepted one (once the
+// debounce time is over).
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::interrupt::{CriticalSection, Mutex};
+use cortex_m::peripheral::NVIC;
+use hal_button::{Chord, ChordDetector};
+use heapless::mpmc::Q32;
+use stm32f1xx_hal::pac::{interrupt, Interrupt, EXTI, GPIOB, GPIOC};
+
+use crate::clock::Clock;
+
+// Minimum time between two edges of the same button
+const EDGE_DEBOUNCE_MS: u32 = 20;
+
+// EXTI lines of the buttons
+const BUTTON_1_LINE: u32 = 13; // PB13
+const BUTTON_2_LINE: u32 = 15; // PC15
+const BUTTON_3_LINE: u32 = 14; // PC14
+const BUTTONS_EXTI_MASK: u32 = (1 << BUTTON_1_LINE) | (1 << BUTTON_2_LINE) | (1 << BUTTON_3_LINE);
+
+static QUEUE: Q32<ButtonEdge> = Q32::new();
+static DROPPED_EDGES: AtomicU32 = AtomicU32::new(0);
+// The clock used by the interrupt handler to timestamp the edges
+static CLOCK: Mutex<Cell<Option<Clock>>> = Mutex::new(Cell::new(None));
+// Last accepted state of every button and when it changed
+static BUTTON_STATES: Mutex<Cell<[(bool, u32); 3]>> = Mutex::new(Cell::new([(false, 0); 3]));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonId {
+    Button1,
+    Button2,
+    Button3,
+}
+
+impl ButtonId {
+    // Index of the button in the chords
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+// The chords of a single button
+pub const BUTTON_1: Chord = Chord::of(&[ButtonId::Button1.index()]);
+pub const BUTTON_2: Chord = Chord::of(&[ButtonId::Button2.index()]);
+pub const BUTTON_3: Chord = Chord::of(&[ButtonId::Button3.index()]);
+
+/// A change of the state of a button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ButtonEdge {
+    pub button: ButtonId,
+    /// True if the button has been pressed, false if it has been released
+    pub pressed: bool,
+    /// Time of the edge, from the board clock
+    pub time_ms: u32,
+}
+
+// Handle to take the button edges queued by the interrupt handler
+pub struct ButtonEvents {
+    chords: ChordDetector,
+}
+
+impl ButtonEvents {
+    // The EXTI lines of the buttons must have been configured before enabling the interrupt
+    pub(crate) fn new(clock: Clock) -> Self {
+        cortex_m::interrupt::free(|cs| CLOCK.borrow(cs).set(Some(clock)));
+        // SAFETY: the handler only uses the queue and the registers of the buttons
+        unsafe { NVIC::unmask(Interrupt::EXTI15_10) };
+        ButtonEvents {
+            chords: ChordDetector::new(),
+        }
+    }
+
+    // Take the oldest button edge, if any
+    pub fn pop(&mut self) -> Option<ButtonEdge> {
+        // Queue the final states missed by the interrupt handler
+        cortex_m::interrupt::free(|cs| update_buttons(cs, BUTTONS_EXTI_MASK));
+        QUEUE.dequeue()
+    }
+
+    // Take the edges until a press, and return the button pressed. The releases are discarded.
+    pub fn pop_press(&mut self) -> Option<ButtonId> {
+        while let Some(edge) = self.pop() {
+            if edge.pressed {
+                return Some(edge.button);
+            }
+        }
+        None
+    }
+
+    // Take the edges until a chord is complete (all its buttons released), and return it. A single
+    // button gives a chord of one button (e.g. BUTTON_1).
+    pub fn pop_chord(&mut self) -> Option<Chord> {
+        while let Some(edge) = self.pop() {
+            if let Some(chord) = self.chords.update(edge.button.index(), edge.pressed) {
+                return Some(chord);
+            }
+        }
+        None
+    }
+
+    // Discard the queued edges and the chord in progress, e.g. when entering a state so the presses
+    // of the previous one are not taken as new ones
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+        self.chords.reset();
+    }
+
+    // Number of edges dropped because the queue was full
+    pub fn dropped(&self) -> u32 {
+        DROPPED_EDGES.load(Ordering::Relaxed)
+    }
+}
+
+#[interrupt]
+fn EXTI15_10() {
+    // SAFETY: writing the pending register only clears the written bits (the other EXTI lines are
+    // not modified)
+    let exti = unsafe { &*EXTI::ptr() };
+    let pending = exti.pr.read().bits() & BUTTONS_EXTI_MASK;
+    exti.pr.write(|w| unsafe { w.bits(pending) });
+
+    cortex_m::interrupt::free(|cs| update_buttons(cs, pending));
+}
+
+// Read the buttons of the given EXTI lines and queue an edge for every button whose state differs
+// from the last accepted one, unless it changed less than EDGE_DEBOUNCE_MS ago
+fn update_buttons(cs: &CriticalSection, lines: u32) {
+    let time_ms = match CLOCK.borrow(cs).get() {
+        Some(clock) => clock.now_ms(),
+        None => return,
+    };
+    // SAFETY: the input data registers are read only
+    let gpiob_input = unsafe { (*GPIOB::ptr()).idr.read().bits() };
+    let gpioc_input = unsafe { (*GPIOC::ptr()).idr.read().bits() };
+
+    let mut states = BUTTON_STATES.borrow(cs).get();
+    let buttons = [
+        (ButtonId::Button1, BUTTON_1_LINE, gpiob_input),
+        (ButtonId::Button2, BUTTON_2_LINE, gpioc_input),
+        (ButtonId::Button3, BUTTON_3_LINE, gpioc_input),
+    ];
+    for ((button, line, input), state) in buttons.into_iter().zip(states.iter_mut()) {
+        if lines & (1 << line) == 0 {
+            continue;
+        }
+        // The buttons have pull-down resistors, they are high when pressed
+        let pressed = input & (1 << line) != 0;
+        let (last_pressed, last_time_ms) = *state;
+        if pressed == last_pressed || time_ms.wrapping_sub(last_time_ms) < EDGE_DEBOUNCE_MS {
+            continue;
+        }
+        *state = (pressed, time_ms);
+
+        let edge = ButtonEdge {
+            button,
+            pressed,
+            time_ms,
+        };
+        if QUEUE.enqueue(edge).is_err() {
+            DROPPED_EDGES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    BUTTON_STATES.borrow(cs).set(states);
+}
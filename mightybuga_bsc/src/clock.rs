@@ -0,0 +1,50 @@
+// Monotonic clock based on the DWT cycle counter of the Cortex-M3.
+//
+// SysTick is already used by the delay provider and all the timers are used by the motors,
+// encoders and buzzer, so the time is taken from the DWT cycle counter. It is a 32 bits counter
+// that overflows every ~59 seconds at 72 MHz, so the elapsed cycles are accumulated in a 64 bits
+// counter every time the clock is read. The clock must be read at least once per overflow period
+// to not lose time, which is always the case when it is used from the control loops.
+
+use core::cell::Cell;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::DWT;
+
+// (last read of the cycle counter, accumulated cycles since boot)
+static CYCLES: Mutex<Cell<(u32, u64)>> = Mutex::new(Cell::new((0, 0)));
+
+// The clock is a copyable handle, so every part of the application can have its own one
+#[derive(Clone, Copy)]
+pub struct Clock {
+    cycles_per_ms: u32,
+}
+
+impl Clock {
+    // The DWT cycle counter must have been enabled before creating the clock
+    pub(crate) fn new(sysclk_hz: u32) -> Self {
+        Clock {
+            cycles_per_ms: sysclk_hz / 1_000,
+        }
+    }
+
+    // Milliseconds since the board was initialized. It overflows after ~49 days.
+    pub fn now_ms(&self) -> u32 {
+        (self.now_cycles() / self.cycles_per_ms as u64) as u32
+    }
+
+    // Microseconds since the board was initialized
+    pub fn now_us(&self) -> u64 {
+        self.now_cycles() * 1_000 / self.cycles_per_ms as u64
+    }
+
+    fn now_cycles(&self) -> u64 {
+        cortex_m::interrupt::free(|cs| {
+            let cycles = CYCLES.borrow(cs);
+            let (last, total) = cycles.get();
+            let now = DWT::cycle_count();
+            let total = total + now.wrapping_sub(last) as u64;
+            cycles.set((now, total));
+            total
+        })
+    }
+}
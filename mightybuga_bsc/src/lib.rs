@@ -8,7 +8,7 @@
 pub use stm32f1xx_hal as hal;
 
 use hal::adc::Adc;
-use hal::gpio::PullDown;
+use hal::gpio::{Edge, ExtiPin, PullDown};
 use hal::pac::*;
 use hal::prelude::*;
 use hal::serial::*;
@@ -23,17 +23,41 @@ use engine::engine::Engine;
 use engine::motor::Motor;
 use stm32f1xx_hal::timer::PwmChannel;
 
+#[cfg(not(feature = "qtr-8rc"))]
 mod light_sensor_array;
+#[cfg(not(feature = "qtr-8rc"))]
 use light_sensor_array::LightSensorArray;
 
+// The RC version of the sensor array (QTR-8RC) is selected with the "qtr-8rc" feature
+#[cfg(feature = "qtr-8rc")]
+mod rc_light_sensor_array;
+#[cfg(feature = "qtr-8rc")]
+use rc_light_sensor_array::RcLightSensorArray as LightSensorArray;
+use light_sensor_array_controller::AcquisitionMode;
+
 mod battery_sensor;
 use battery_sensor::BatterySensor;
+use battery_sensor_controller::conversion::AdcConversion;
+use battery_sensor_controller::level_detector::BatteryLevelDetector;
+use battery_sensor_controller::state_of_charge::StateOfChargeEstimator;
 
 pub use crate::hal::*;
 
 pub mod timer_based_buzzer;
 use timer_based_buzzer::TimerBasedBuzzer;
 
+pub mod clock;
+use clock::Clock;
+
+pub mod button_events;
+use button_events::ButtonEvents;
+
+pub mod buffered_serial;
+use buffered_serial::BufferedSerial;
+
+pub mod settings_storage;
+use settings_storage::SettingsStorage;
+
 pub use hal_encoder_stm32f1xx::tim2_to_tim5::*;
 
 pub mod prelude {
@@ -82,16 +106,12 @@ pub struct Mightybuga_BSC {
     // LEDs
     pub led_d1: gpio::Pin<'C', 13, gpio::Output>,
     pub led_d2: gpio::Pin<'B', 12, gpio::Output>,
-    // UART
-    pub serial: Serial<
-        USART1,
-        (
-            gpio::Pin<'A', 9, gpio::Alternate>,
-            gpio::Pin<'A', 10, gpio::Input>,
-        ),
-    >,
+    // UART, the transmission is buffered and interrupt driven
+    pub serial: BufferedSerial,
     // delay provider
     pub delay: SysDelay,
+    // monotonic clock
+    pub clock: Clock,
     // Buzzer
     pub buzzer: TimerBasedBuzzer,
     // Engine
@@ -111,6 +131,8 @@ pub struct Mightybuga_BSC {
     pub btn_1: hal_button::Button<gpio::Pin<'B', 13, gpio::Input<PullDown>>, false>,
     pub btn_2: hal_button::Button<gpio::Pin<'C', 15, gpio::Input<PullDown>>, false>,
     pub btn_3: hal_button::Button<gpio::Pin<'C', 14, gpio::Input<PullDown>>, false>,
+    // Button edges queued by the buttons interrupt
+    pub button_events: ButtonEvents,
     // Encoders
     pub encoder_r: IncrementalEncoder,
     pub encoder_l: IncrementalEncoder,
@@ -118,6 +140,8 @@ pub struct Mightybuga_BSC {
     pub light_sensor_array: LightSensorArray,
     // Battery sensor
     pub battery_sensor: BatterySensor,
+    // Settings stored in the flash
+    pub settings_storage: SettingsStorage,
 }
 
 impl Mightybuga_BSC {
@@ -141,10 +165,15 @@ impl Mightybuga_BSC {
             .sysclk(72.MHz())
             .freeze(&mut flash.acr);
 
-        let cp = cortex_m::Peripherals::take().unwrap();
+        let mut cp = cortex_m::Peripherals::take().unwrap();
         let mut delay = cp.SYST.delay(&clocks);
         delay.delay(300.millis());
 
+        // The monotonic clock counts the core cycles with the DWT
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+        let clock = Clock::new(clocks.sysclk().raw());
+
         // GPIO ports
         let mut gpioa = dp.GPIOA.split();
         let mut gpiob = dp.GPIOB.split();
@@ -208,12 +237,29 @@ impl Mightybuga_BSC {
         afio.mapr
             .modify_mapr(|_, w| unsafe { w.tim3_remap().bits(0b10) });
         let buzzer_pin = pb4.into_alternate_push_pull(&mut gpiob.crl);
-        let buzzer = TimerBasedBuzzer::new(dp.TIM3, buzzer_pin);
-
-        // Button configurations
-        let btn_1 = hal_button::Button::new(gpiob.pb13.into_pull_down_input(&mut gpiob.crh));
-        let btn_2 = hal_button::Button::new(gpioc.pc15.into_pull_down_input(&mut gpioc.crh));
-        let btn_3 = hal_button::Button::new(gpioc.pc14.into_pull_down_input(&mut gpioc.crh));
+        // TIM3 is on the APB1 bus, its clock is twice the bus clock when the bus is divided
+        let buzzer = TimerBasedBuzzer::new(dp.TIM3, buzzer_pin, clocks.pclk1_tim().raw());
+
+        // Button configurations. Every edge of the buttons triggers an interrupt (EXTI15_10) that
+        // queues it in the button events.
+        let mut exti = dp.EXTI;
+        let mut btn_1_pin = gpiob.pb13.into_pull_down_input(&mut gpiob.crh);
+        let mut btn_2_pin = gpioc.pc15.into_pull_down_input(&mut gpioc.crh);
+        let mut btn_3_pin = gpioc.pc14.into_pull_down_input(&mut gpioc.crh);
+        btn_1_pin.make_interrupt_source(&mut afio);
+        btn_1_pin.trigger_on_edge(&mut exti, Edge::RisingFalling);
+        btn_1_pin.enable_interrupt(&mut exti);
+        btn_2_pin.make_interrupt_source(&mut afio);
+        btn_2_pin.trigger_on_edge(&mut exti, Edge::RisingFalling);
+        btn_2_pin.enable_interrupt(&mut exti);
+        btn_3_pin.make_interrupt_source(&mut afio);
+        btn_3_pin.trigger_on_edge(&mut exti, Edge::RisingFalling);
+        btn_3_pin.enable_interrupt(&mut exti);
+        let button_events = ButtonEvents::new(clock);
+
+        let btn_1 = hal_button::Button::new(btn_1_pin);
+        let btn_2 = hal_button::Button::new(btn_2_pin);
+        let btn_3 = hal_button::Button::new(btn_3_pin);
 
         // Encoder right
         let encoder_r = IncrementalEncoder::new(
@@ -244,6 +290,7 @@ impl Mightybuga_BSC {
             Err(_) => panic!("Couldn't get the adc arc"),
         };
 
+        #[cfg(not(feature = "qtr-8rc"))]
         let light_sensor_array = LightSensorArray {
             led: gpiob.pb1.into_push_pull_output(&mut gpiob.crl),
             sensor_0: gpioa.pa0.into_analog(&mut gpioa.crl),
@@ -255,19 +302,52 @@ impl Mightybuga_BSC {
             sensor_6: gpioa.pa6.into_analog(&mut gpioa.crl),
             sensor_7: gpioa.pa7.into_analog(&mut gpioa.crl),
             adc: adc_arc.clone(),
+            acquisition_mode: AcquisitionMode::Direct,
+            led_enabled: false,
+            sysclk_hz: clocks.sysclk().raw(),
+        };
+
+        #[cfg(feature = "qtr-8rc")]
+        let light_sensor_array = LightSensorArray {
+            led: gpiob.pb1.into_push_pull_output(&mut gpiob.crl),
+            sensor_0: gpioa.pa0.into_dynamic(&mut gpioa.crl),
+            sensor_1: gpioa.pa1.into_dynamic(&mut gpioa.crl),
+            sensor_2: gpioa.pa2.into_dynamic(&mut gpioa.crl),
+            sensor_3: gpioa.pa3.into_dynamic(&mut gpioa.crl),
+            sensor_4: gpioa.pa4.into_dynamic(&mut gpioa.crl),
+            sensor_5: gpioa.pa5.into_dynamic(&mut gpioa.crl),
+            sensor_6: gpioa.pa6.into_dynamic(&mut gpioa.crl),
+            sensor_7: gpioa.pa7.into_dynamic(&mut gpioa.crl),
+            crl: gpioa.crl,
+            timeout_us: 2500,
+            acquisition_mode: AcquisitionMode::Direct,
+            led_enabled: false,
+            sysclk_hz: clocks.sysclk().raw(),
         };
 
+        // The settings stored in the flash
+        let mut settings_storage = SettingsStorage::new(flash);
+        let settings = settings_storage.load_or_default();
+
         let battery_sensor = BatterySensor {
             sensor_0: gpiob.pb0.into_analog(&mut gpiob.crl),
             adc: adc_arc.clone(),
+            conversion: AdcConversion {
+                calibration_factor: settings.battery_calibration_factor,
+                ..Default::default()
+            },
+            state_of_charge: StateOfChargeEstimator::new(Default::default()),
+            level_detector: BatteryLevelDetector::new(Default::default()),
+            clock,
         };
 
         // Return the initialized struct
         Ok(Mightybuga_BSC {
             led_d1: d1,
             led_d2: d2,
-            serial: serial_uart,
+            serial: BufferedSerial::new(serial_uart),
             delay,
+            clock,
             buzzer,
             engine,
             encoder_r,
@@ -275,8 +355,10 @@ impl Mightybuga_BSC {
             btn_1,
             btn_2,
             btn_3,
+            button_events,
             light_sensor_array,
             battery_sensor,
+            settings_storage,
         })
     }
 }
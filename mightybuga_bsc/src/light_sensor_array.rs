@@ -6,6 +6,10 @@ use crate::{
     ADC_POOL,
 };
 use heapless::pool::arc::Arc;
+use light_sensor_array_controller::{ambient_rejected_light_map, AcquisitionMode};
+
+/// The maximum value given by the 12 bits ADC
+const ADC_FULL_SCALE: u16 = 4095;
 
 /// The LineSensor used to detect the place where the line is located.
 /// It uses 8 analog pins connected to the light intensity sensors, 1 pin for turning on the led in
@@ -25,13 +29,21 @@ pub struct LightSensorArray {
     pub sensor_7: Pin<'A', 7, Analog>, // this sensor is located on the right side of the robot
 
     pub adc: Arc<ADC_POOL>,
+
+    /// How the light maps are acquired (direct reading or ambient light rejection)
+    pub acquisition_mode: AcquisitionMode,
+    /// The led value requested with `set_led`, restored after an ambient rejection acquisition
+    pub led_enabled: bool,
+    /// The core clock frequency, used to wait for the sensors to settle after a led change
+    pub sysclk_hz: u32,
 }
 
-impl light_sensor_array_controller::LightSensorArrayController for LightSensorArray {
-    fn get_light_map(&mut self) -> [u16; 8] {
+impl LightSensorArray {
+    // Read the 8 sensors once
+    fn read_frame(&mut self) -> [u16; 8] {
         let mut adc = self.adc.borrow_mut();
 
-        let light_map = [
+        [
             adc.read(&mut self.sensor_0).unwrap(),
             adc.read(&mut self.sensor_1).unwrap(),
             adc.read(&mut self.sensor_2).unwrap(),
@@ -40,15 +52,52 @@ impl light_sensor_array_controller::LightSensorArrayController for LightSensorAr
             adc.read(&mut self.sensor_5).unwrap(),
             adc.read(&mut self.sensor_6).unwrap(),
             adc.read(&mut self.sensor_7).unwrap(),
-        ];
+        ]
+    }
 
-        light_map
+    // Busy wait, the phototransistors need some time to follow the led changes
+    fn wait_settling(&self, settling_us: u32) {
+        cortex_m::asm::delay(settling_us * (self.sysclk_hz / 1_000_000));
     }
 
-    fn set_led(&mut self, value: bool) -> () {
+    fn write_led(&mut self, value: bool) {
         match value {
             true => self.led.set_high(),
             false => self.led.set_low(),
         }
     }
 }
+
+impl light_sensor_array_controller::LightSensorArrayController for LightSensorArray {
+    fn get_light_map(&mut self) -> [u16; 8] {
+        match self.acquisition_mode {
+            AcquisitionMode::Direct => self.read_frame(),
+            AcquisitionMode::AmbientRejection { settling_us } => {
+                self.write_led(false);
+                self.wait_settling(settling_us);
+                let led_off = self.read_frame();
+
+                self.write_led(true);
+                self.wait_settling(settling_us);
+                let led_on = self.read_frame();
+
+                self.write_led(self.led_enabled);
+
+                ambient_rejected_light_map(led_off, led_on, ADC_FULL_SCALE)
+            }
+        }
+    }
+
+    fn set_led(&mut self, value: bool) -> () {
+        self.led_enabled = value;
+        self.write_led(value);
+    }
+
+    fn set_acquisition_mode(&mut self, mode: AcquisitionMode) {
+        self.acquisition_mode = mode;
+    }
+
+    fn get_acquisition_mode(&self) -> AcquisitionMode {
+        self.acquisition_mode
+    }
+}
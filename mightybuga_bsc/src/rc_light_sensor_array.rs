@@ -0,0 +1,160 @@
+use crate::hal::{
+    gpio::{Cr, Dynamic, Output, Pin},
+    pac::GPIOA,
+};
+use cortex_m::peripheral::DWT;
+use embedded_hal::digital::v2::OutputPin;
+use light_sensor_array_controller::{
+    ambient_rejected_light_map, rc_timing::RcDischargeMeasurement, AcquisitionMode,
+};
+
+/// The light maps are scaled to 12 bits, as the ones read by the ADC from the analog array
+const FULL_SCALE: u16 = 4095;
+
+/// Time driving the sensor lines high to charge the capacitors
+const CHARGE_US: u32 = 10;
+
+// Run `body` for each of the 8 sensor lines, with `line` bound to the pin
+macro_rules! for_each_line {
+    ($array:ident, $line:ident => $($body:tt)*) => {{
+        {
+            let $line = &mut $array.sensor_0;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_1;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_2;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_3;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_4;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_5;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_6;
+            $($body)*
+        }
+        {
+            let $line = &mut $array.sensor_7;
+            $($body)*
+        }
+    }};
+}
+
+/// The line sensor for the RC version of the array (QTR-8RC). It uses the same 8 pins as the
+/// analog array, but as digital lines: they are charged, switched to inputs and the time until each
+/// one reads low is measured. As all the timers are in use, the discharge is timed with the DWT
+/// cycle counter.
+///
+/// The 8 lines are PA0 to PA7, dynamic pins of the HAL switched between output and input with the
+/// GPIOA CRL register, that is kept here. The lines are read all at once from the input register.
+pub struct RcLightSensorArray {
+    /// The output pin used to set the led in the sensor array high
+    pub led: Pin<'B', 1, Output>,
+
+    /// The 8 sensor lines
+    pub sensor_0: Pin<'A', 0, Dynamic>, // this sensor is located on the left side of the robot
+    pub sensor_1: Pin<'A', 1, Dynamic>,
+    pub sensor_2: Pin<'A', 2, Dynamic>,
+    pub sensor_3: Pin<'A', 3, Dynamic>,
+    pub sensor_4: Pin<'A', 4, Dynamic>,
+    pub sensor_5: Pin<'A', 5, Dynamic>,
+    pub sensor_6: Pin<'A', 6, Dynamic>,
+    pub sensor_7: Pin<'A', 7, Dynamic>, // this sensor is located on the right side of the robot
+    /// The configuration register of the sensor lines, to switch their mode
+    pub crl: Cr<'A', false>,
+
+    /// Discharge time read as the full scale (the darkest value)
+    pub timeout_us: u32,
+
+    /// How the light maps are acquired (direct reading or ambient light rejection)
+    pub acquisition_mode: AcquisitionMode,
+    /// The led value requested with `set_led`, restored after an ambient rejection acquisition
+    pub led_enabled: bool,
+    /// The core clock frequency, used to time the discharge
+    pub sysclk_hz: u32,
+}
+
+impl RcLightSensorArray {
+    // Charge the lines and time their discharge
+    fn read_frame(&mut self) -> [u16; 8] {
+        let cycles_per_us = self.sysclk_hz / 1_000_000;
+        // Only the input register is read, the pins are changed through the HAL
+        let gpioa = unsafe { &*GPIOA::ptr() };
+
+        for_each_line!(self, line => line.make_push_pull_output(&mut self.crl));
+        // The lines are outputs now, setting them can't fail
+        for_each_line!(self, line => let _ = line.set_high(););
+        cortex_m::asm::delay(CHARGE_US * cycles_per_us);
+
+        // An interrupt while polling would make the lines look darker than they are
+        cortex_m::interrupt::free(|_| {
+            let mut measurement = RcDischargeMeasurement::new(self.timeout_us * cycles_per_us);
+            let start = DWT::cycle_count();
+            for_each_line!(self, line => line.make_floating_input(&mut self.crl));
+            while measurement.sample(
+                DWT::cycle_count().wrapping_sub(start),
+                gpioa.idr.read().bits() as u8,
+            ) {}
+
+            measurement.light_map(FULL_SCALE)
+        })
+    }
+
+    // Busy wait, the phototransistors need some time to follow the led changes
+    fn wait_settling(&self, settling_us: u32) {
+        cortex_m::asm::delay(settling_us * (self.sysclk_hz / 1_000_000));
+    }
+
+    fn write_led(&mut self, value: bool) {
+        match value {
+            true => self.led.set_high(),
+            false => self.led.set_low(),
+        }
+    }
+}
+
+impl light_sensor_array_controller::LightSensorArrayController for RcLightSensorArray {
+    fn get_light_map(&mut self) -> [u16; 8] {
+        match self.acquisition_mode {
+            AcquisitionMode::Direct => self.read_frame(),
+            AcquisitionMode::AmbientRejection { settling_us } => {
+                self.write_led(false);
+                self.wait_settling(settling_us);
+                let led_off = self.read_frame();
+
+                self.write_led(true);
+                self.wait_settling(settling_us);
+                let led_on = self.read_frame();
+
+                self.write_led(self.led_enabled);
+
+                ambient_rejected_light_map(led_off, led_on, FULL_SCALE)
+            }
+        }
+    }
+
+    fn set_led(&mut self, value: bool) {
+        self.led_enabled = value;
+        self.write_led(value);
+    }
+
+    fn set_acquisition_mode(&mut self, mode: AcquisitionMode) {
+        self.acquisition_mode = mode;
+    }
+
+    fn get_acquisition_mode(&self) -> AcquisitionMode {
+        self.acquisition_mode
+    }
+}
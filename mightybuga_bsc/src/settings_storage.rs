@@ -0,0 +1,105 @@
+// The settings storage keeps the settings of the robot (e.g. calibration values) in the last page of
+// the flash, so they survive a power cycle.
+//
+// The page is reserved in memory.x (the firmware can't use it). The settings are stored as 32 bits
+// words: a magic number, the settings fields and a checksum. If the page has never been written, or
+// it has been written by an older firmware with a different layout, the magic number or the
+// checksum don't match and the default settings are used. The default settings are used too if
+// the stored calibration factor is out of the valid band, so a wrong calibration can't keep the
+// robot reading a wrong battery voltage forever.
+
+use stm32f1xx_hal::flash::{self, FlashSize, SectorSize};
+
+use battery_sensor_controller::conversion::{is_valid_calibration_factor, UNIT_CALIBRATION_FACTOR};
+
+// The STM32F103C8 has 64 pages of 1 KiB, the settings are in the last one
+const SETTINGS_OFFSET: u32 = 63 * 1024;
+const SETTINGS_PAGE_SIZE: usize = 1024;
+
+// Changed every time the layout of the settings changes
+const SETTINGS_MAGIC: u32 = 0x5253_0001;
+
+// Magic number, fields and checksum
+const SETTINGS_WORDS: usize = 3;
+
+/// The settings of the robot that are stored in the flash
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// Calibration factor of the battery voltage divider
+    /// (see `battery_sensor_controller::conversion::AdcConversion`)
+    pub battery_calibration_factor: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            battery_calibration_factor: UNIT_CALIBRATION_FACTOR,
+        }
+    }
+}
+
+impl Settings {
+    fn to_words(self) -> [u32; SETTINGS_WORDS] {
+        let mut words = [SETTINGS_MAGIC, self.battery_calibration_factor, 0];
+        words[SETTINGS_WORDS - 1] = checksum(&words[..SETTINGS_WORDS - 1]);
+        words
+    }
+
+    fn from_words(words: &[u32; SETTINGS_WORDS]) -> Option<Self> {
+        if words[0] != SETTINGS_MAGIC
+            || words[SETTINGS_WORDS - 1] != checksum(&words[..SETTINGS_WORDS - 1])
+            || !is_valid_calibration_factor(words[1])
+        {
+            return None;
+        }
+        Some(Settings {
+            battery_calibration_factor: words[1],
+        })
+    }
+}
+
+// A simple checksum, so a half written page is not taken as valid settings
+fn checksum(words: &[u32]) -> u32 {
+    words
+        .iter()
+        .fold(0x1234_5678u32, |sum, &word| sum.rotate_left(5) ^ word)
+}
+
+pub struct SettingsStorage {
+    flash: flash::Parts,
+}
+
+impl SettingsStorage {
+    pub fn new(flash: flash::Parts) -> Self {
+        SettingsStorage { flash }
+    }
+
+    /// Load the stored settings, or None if there are no valid settings in the flash
+    pub fn load(&mut self) -> Option<Settings> {
+        let writer = self.flash.writer(SectorSize::Sz1K, FlashSize::Sz64K);
+        let bytes = writer.read(SETTINGS_OFFSET, SETTINGS_WORDS * 4).ok()?;
+
+        let mut words = [0u32; SETTINGS_WORDS];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Settings::from_words(&words)
+    }
+
+    /// Load the stored settings, or the default ones if there are no valid settings in the flash
+    pub fn load_or_default(&mut self) -> Settings {
+        self.load().unwrap_or_default()
+    }
+
+    /// Erase the settings page and write the settings
+    pub fn store(&mut self, settings: &Settings) -> Result<(), flash::Error> {
+        let mut bytes = [0u8; SETTINGS_WORDS * 4];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(settings.to_words()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        let mut writer = self.flash.writer(SectorSize::Sz1K, FlashSize::Sz64K);
+        writer.erase(SETTINGS_OFFSET, SETTINGS_PAGE_SIZE)?;
+        writer.write(SETTINGS_OFFSET, &bytes)
+    }
+}
@@ -1,7 +1,8 @@
 // The low lever, timer based buzzer module represents the low level interface to the buzzer.
 // It provides a simple interface to control the buzzer, where you can turn it on and off.
 // and also change the frequency of the sound it produces by changing the prescaler
-// and the compare value of the timer.
+// and the compare value of the timer, or giving the frequency (or note) to play.
+// The volume is set with the duty cycle of the PWM signal (see volume).
 //
 // The buzzer is connected to the timer 3 channel 1. Gpio pin PB4 is connected to the buzzer.
 
@@ -12,6 +13,12 @@ use stm32f1xx_hal::{
 };
 
 // import the necessary from the timer based buzzer interface
+pub use timer_based_buzzer_interface::melody;
+pub use timer_based_buzzer_interface::pitch;
+pub use timer_based_buzzer_interface::rtttl;
+pub use timer_based_buzzer_interface::status_sounds;
+pub use timer_based_buzzer_interface::volume;
+use volume::{compare_for_volume, MAX_VOLUME};
 pub use timer_based_buzzer_interface::TimerBasedBuzzerInterface;
 
 // the struct that represents the timer based buzzer
@@ -20,11 +27,15 @@ pub struct TimerBasedBuzzer {
     timer: TIM3,
     // the pin connected to the buzzer
     _pin: PB4<Alternate<PushPull>>,
+    // the frequency of the clock of the timer, used to calculate the registers for a frequency
+    timer_clock_hz: u32,
+    // the volume, kept when the frequency changes
+    volume: u8,
 }
 
 // the implementation of the timer based buzzer
 impl TimerBasedBuzzer {
-    pub fn new(timer: TIM3, pin: PB4<Alternate<PushPull>>) -> Self {
+    pub fn new(timer: TIM3, pin: PB4<Alternate<PushPull>>, timer_clock_hz: u32) -> Self {
         // Configure the PWD peripheral at PAC level:
 
         // Set timer 3 mode to no divisor (72MHz), Edge-aligned, up-counting,
@@ -55,7 +66,12 @@ impl TimerBasedBuzzer {
         // Set duty cycle to 50% for channel 1
         timer.ccr1().write(|w| w.ccr().bits(counter / 2));
 
-        TimerBasedBuzzer { timer, _pin: pin }
+        TimerBasedBuzzer {
+            timer,
+            _pin: pin,
+            timer_clock_hz,
+            volume: MAX_VOLUME,
+        }
     }
 }
 
@@ -81,7 +97,24 @@ impl TimerBasedBuzzerInterface for TimerBasedBuzzer {
         self.timer.psc.write(|w| w.psc().bits(prescaler));
         // Set the auto-reload value for the note
         self.timer.arr.write(|w| w.arr().bits(compare));
-        // Set duty cycle to 50% for channel 1
-        self.timer.ccr1().write(|w| w.ccr().bits(compare / 2));
+        // Set the duty cycle for the volume (50% at full volume) for channel 1
+        let duty = compare_for_volume(compare, self.volume);
+        self.timer.ccr1().write(|w| w.ccr().bits(duty));
+    }
+
+    fn timer_clock_hz(&self) -> u32 {
+        self.timer_clock_hz
+    }
+
+    fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(MAX_VOLUME);
+        // Change the duty cycle of the frequency being played
+        let compare = self.timer.arr.read().arr().bits();
+        let duty = compare_for_volume(compare, self.volume);
+        self.timer.ccr1().write(|w| w.ccr().bits(duty));
+    }
+
+    fn volume(&self) -> u8 {
+        self.volume
     }
 }